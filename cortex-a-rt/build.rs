@@ -3,15 +3,150 @@ use std::fs::File;
 use std::io::Write;
 use std::path::PathBuf;
 
+/// Stack size of each core, unless overridden by `CORTEX_A_RT_STACK_SIZE`
+const DEFAULT_STACK_SIZE: u64 = 64 * 1024;
+
+/// Heap size, unless overridden by `CORTEX_A_RT_HEAP_SIZE`
+const DEFAULT_HEAP_SIZE: u64 = 16 * 1024 * 1024;
+
+/// Page size of the 64 KiB translation granule
+const PAGE_SIZE_64K: u64 = 64 * 1024;
+
+/// Page size of the 4 KiB translation granule, selected with the `granule-4k` feature
+const PAGE_SIZE_4K: u64 = 4 * 1024;
+
+/// Number of cores, each of which gets its own stack
+const NUM_CORES: u64 = 4;
+
+/// Minimum size of the unmapped guard region below each core's stack, which catches stack
+/// overflows. Larger than a 4 KiB page, so that big stack frames do not skip over it.
+const MIN_STACK_GUARD_SIZE: u64 = 64 * 1024;
+
+/// Log2 of the size of the small stack of each core, used for reporting stack overflows. The size
+/// is a power of two, so that exception.s finds the stack of a core with a shift.
+const EMERGENCY_STACK_SHIFT: u32 = 14;
+
+/// Start of the higher half with the 64 KiB granule, which is the 42 bit range translated through
+/// TTBR1_EL1 by the root table. Must match `KERNEL_VIRT_OFFSET` of the translation table.
+const KERNEL_VIRT_OFFSET_64K: u64 = 0xFFFF_FC00_0000_0000;
+
+/// Start of the higher half with the 4 KiB granule, which is the 48 bit range translated through
+/// TTBR1_EL1 by the root table.
+const KERNEL_VIRT_OFFSET_4K: u64 = 0xFFFF_0000_0000_0000;
+
+/// Parses a size given in decimal or hex (`0x` prefix), with an optional `K` or `M` suffix.
+fn parse_size(s: &str) -> Option<u64> {
+    let s = s.trim();
+
+    let (s, multiplier) = if let Some(s) = s.strip_suffix(|c: char| c == 'K' || c == 'k') {
+        (s, 1024)
+    } else if let Some(s) = s.strip_suffix(|c: char| c == 'M' || c == 'm') {
+        (s, 1024 * 1024)
+    } else {
+        (s, 1)
+    };
+
+    let value = if let Some(hex) = s.strip_prefix("0x") {
+        u64::from_str_radix(hex, 16).ok()?
+    } else {
+        s.parse::<u64>().ok()?
+    };
+
+    value.checked_mul(multiplier)
+}
+
+/// Reads a size from the environment variable, falling back to the default value.
+fn env_size(name: &str, default: u64) -> u64 {
+    println!("cargo:rerun-if-env-changed={}", name);
+
+    match env::var(name) {
+        Ok(value) => {
+            parse_size(&value).unwrap_or_else(|| panic!("Invalid {} value: {:?}", name, value))
+        }
+        Err(_) => default,
+    }
+}
+
 fn main() {
-    // Put the linker script somewhere the linker can find it
+    // Put the linker script and the fragments, which custom linker scripts include as well,
+    // somewhere the linker can find them
     let out = &PathBuf::from(env::var_os("OUT_DIR").unwrap());
-    let link_x = include_bytes!("link.x.in");
-    let mut f = File::create(out.join("link.x")).unwrap();
-    f.write_all(link_x).unwrap();
+    for &(name, contents) in &[
+        ("link.x", &include_bytes!("link.x.in")[..]),
+        ("fixup_el0.x", &include_bytes!("fixup_el0.x.in")[..]),
+        ("heap_stacks.x", &include_bytes!("heap_stacks.x.in")[..]),
+    ] {
+        let mut f = File::create(out.join(name)).unwrap();
+        f.write_all(contents).unwrap();
+    }
+
+    // Memory configuration included by the linker script. Stacks are mapped with page granularity.
+    let granule_4k = env::var_os("CARGO_FEATURE_GRANULE_4K").is_some();
+    let page_size = if granule_4k {
+        PAGE_SIZE_4K
+    } else {
+        PAGE_SIZE_64K
+    };
+
+    // The boot translation table of the `higher-half` feature takes one table with the 64 KiB
+    // granule, and a level 0 and a level 1 table with the 4 KiB granule.
+    let higher_half = env::var_os("CARGO_FEATURE_HIGHER_HALF").is_some();
+    let (kernel_virt_offset, boot_tables_size) = match (higher_half, granule_4k) {
+        (false, _) => (0, 0),
+        (true, false) => (KERNEL_VIRT_OFFSET_64K, page_size),
+        (true, true) => (KERNEL_VIRT_OFFSET_4K, 2 * page_size),
+    };
+
+    let stack_size = env_size("CORTEX_A_RT_STACK_SIZE", DEFAULT_STACK_SIZE);
+    let heap_size = env_size("CORTEX_A_RT_HEAP_SIZE", DEFAULT_HEAP_SIZE);
+
+    for &(name, size) in &[
+        ("CORTEX_A_RT_STACK_SIZE", stack_size),
+        ("CORTEX_A_RT_HEAP_SIZE", heap_size),
+    ] {
+        if size == 0 || size % page_size != 0 {
+            panic!(
+                "{} must be a non-zero multiple of {:#x}, got {:#x}",
+                name, page_size, size
+            );
+        }
+    }
+
+    let mut f = File::create(out.join("memory_config.x")).unwrap();
+    writeln!(f, "/* Generated by cortex-a-rt build.rs */").unwrap();
+    writeln!(f, "__page_size = {:#x};", page_size).unwrap();
+    writeln!(f, "__core_stack_size = {:#x};", stack_size).unwrap();
+    writeln!(f, "__heap_size = {:#x};", heap_size).unwrap();
+    writeln!(f, "__num_cores = {};", NUM_CORES).unwrap();
+    writeln!(
+        f,
+        "__stack_guard_size = {:#x};",
+        page_size.max(MIN_STACK_GUARD_SIZE)
+    )
+    .unwrap();
+    writeln!(
+        f,
+        "__core_stack_stride = __stack_guard_size + __core_stack_size;"
+    )
+    .unwrap();
+    writeln!(
+        f,
+        "__emergency_stack_size = {:#x};",
+        1u64 << EMERGENCY_STACK_SHIFT
+    )
+    .unwrap();
+    writeln!(f, "__kernel_virt_offset = {:#x};", kernel_virt_offset).unwrap();
+    writeln!(f, "__boot_tables_size = {:#x};", boot_tables_size).unwrap();
+
+    // Constants of exception.s, which are shared with the linker script
+    let mut f = File::create(out.join("exception_config.s")).unwrap();
+    writeln!(f, "// Generated by cortex-a-rt build.rs").unwrap();
+    writeln!(f, ".equ _emergency_stack_shift, {}", EMERGENCY_STACK_SHIFT).unwrap();
 
     println!("cargo:rustc-link-search={}", out.display());
 
     println!("cargo:rerun-if-changed=build.rs");
     println!("cargo:rerun-if-changed=link.x.in");
+    println!("cargo:rerun-if-changed=fixup_el0.x.in");
+    println!("cargo:rerun-if-changed=heap_stacks.x.in");
 }
@@ -18,7 +18,165 @@
 	add	\register, \register, #:lo12:\symbol
 .endm
 
+// Set the stack pointer of the executing core and return its address in x0.
+//
+// Every core gets its own stack of `__core_stack_size` bytes, preceded by a guard page. The stack of
+// core N grows down from `__stacks_start + (N + 1) * __core_stack_stride`. Clobbers x1 and x2.
+.macro SET_CORE_STACK
+	mrs	x1, MPIDR_EL1
+	and	x1, x1, _core_id_mask
+	add	x1, x1, #1
+	ADR_REL	x0, __stacks_start
+	ldr	x2, =__core_stack_stride
+	madd	x0, x1, x2, x0
+	mov	sp, x0
+.endm
+
+// Drop from EL3 to EL2 if the core executes in EL3, which is the case when started by a custom
+// armstub. Does nothing in EL2 and EL1. Clobbers x0.
+.macro LEAVE_EL3
+	mrs	x0, CurrentEL
+	cmp	x0, _EL3
+	b.ne	1f
+
+	// The architectural timer frequency can only be set from EL3. The RPi4 crystal runs at 54 MHz.
+	ldr	x0, =54000000
+	msr	CNTFRQ_EL0, x0
+
+	// Do not trap FP/SIMD at EL3.
+	msr	CPTR_EL3, xzr
+
+	// SCR_EL3: lower ELs are non-secure (NS) and AArch64 (RW), HVC enabled (HCE), SMC disabled
+	// (SMD), RES1 bits [5:4] set.
+	mov	x0, #((1 << 10) | (1 << 8) | (1 << 7) | (0b11 << 4) | 1)
+	msr	SCR_EL3, x0
+
+	// SCTLR_EL2: RES1 bits set, MMU and caches off, little endian.
+	ldr	x0, =0x30C50830
+	msr	SCTLR_EL2, x0
+
+	// Fake an exception return to EL2h with all interrupts masked.
+	mov	x0, #((0b1111 << 6) | 0b1001)
+	msr	SPSR_EL3, x0
+	adr	x0, 1f
+	msr	ELR_EL3, x0
+	eret
+1:
+.endm
+
+// Disable trapping of FP/SIMD instructions at EL2 and EL1, so that code built for the hard-float
+// `aarch64-unknown-none` target can use them. EL2 is only configured if the core executes in EL2.
+// Clobbers x0.
+.macro ENABLE_FP
+	mrs	x0, CurrentEL
+	cmp	x0, _EL2
+	b.ne	1f
+
+	// CPTR_EL2: RES1 bits set, TFP (bit 10) cleared.
+	mov	x0, #0x33ff
+	msr	CPTR_EL2, x0
+1:
+	// CPACR_EL1.FPEN (bits [21:20]) = 0b11, no trapping at EL0 and EL1.
+	mov	x0, #(0b11 << 20)
+	msr	CPACR_EL1, x0
+
+	isb
+.endm
+
+// Populate the boot translation table of the `higher-half` feature, which maps the first 1 GiB as
+// normal cacheable memory. The same table is used for TTBR0_EL1 and TTBR1_EL1, so the memory is
+// identity mapped and mapped at its high alias `__kernel_virt_offset + x`. Clobbers x0 - x3.
+.macro POPULATE_BOOT_TABLES
+	// The MMU is off, so the stores go straight to memory.
+	ADR_REL	x0, __boot_tables_start
+	ADR_REL	x1, __boot_tables_end_exclusive
+1:
+	stp	xzr, xzr, [x0], #16
+	cmp	x0, x1
+	b.lo	1b
+
+	ADR_REL	x0, __boot_tables_start
+	mov	x2, _BOOT_BLOCK_ATTRS
+.if _GRANULE_4K
+	// Level 0 entry 0 points to the level 1 table, whose entry 0 is a 1 GiB block.
+	add	x1, x0, #0x1000
+	orr	x3, x1, #0b11
+	str	x3, [x0]
+	str	x2, [x1]
+.else
+	// Level 2 entries 0 and 1 are 512 MiB blocks.
+	str	x2, [x0]
+	mov	x3, #(1 << 29)
+	add	x2, x2, x3
+	str	x2, [x0, #8]
+.endif
+.endm
+
+// Switch on the MMU and caches of EL1 with the boot translation table, which the runtime replaces
+// once it has populated its own tables. Clobbers x0 - x2.
+.macro ENABLE_BOOT_MMU
+	// Attribute 0 is device nGnRE and attribute 1 is normal cacheable memory, as set up by mmu.rs.
+	mov	x0, #0xFF04
+	msr	MAIR_EL1, x0
+
+	// The physical address size reported by the core, capped at 48 bits.
+	mrs	x1, ID_AA64MMFR0_EL1
+	and	x1, x1, #0xF
+	mov	x2, #0b0101
+	cmp	x1, x2
+	csel	x1, x1, x2, lo
+	ldr	x0, =_BOOT_TCR
+	orr	x0, x0, x1, lsl #32
+	msr	TCR_EL1, x0
+
+	ADR_REL	x0, __boot_tables_start
+	msr	TTBR0_EL1, x0
+	msr	TTBR1_EL1, x0
+
+	// Discard stale TLB entries, such as left behind by the firmware.
+	tlbi	vmalle1
+	dsb	nsh
+	isb
+
+	// SCTLR_EL1: MMU (M), data cache (C) and instruction cache (I) on.
+	mrs	x0, SCTLR_EL1
+	ldr	x1, =((1 << 12) | (1 << 2) | 1)
+	orr	x0, x0, x1
+	msr	SCTLR_EL1, x0
+	isb
+.endm
+
+// Move the stack pointer to its high alias and continue at the link address of the entry function.
+// Clobbers x0 and x1.
+.macro ENTER_HIGHER_HALF entry
+	ldr	x0, =__kernel_virt_offset
+	mov	x1, sp
+	add	sp, x1, x0
+	ldr	x0, =\entry
+	br	x0
+.endm
+
+// Block descriptor attributes of the boot translation table: access flag (AF), inner shareable
+// (SH), AttrIndx 1 and valid.
+.equ _BOOT_BLOCK_ATTRS, (1 << 10) | (0b11 << 8) | (1 << 2) | 1
+
+// TCR_EL1 of the boot translation table without IPS. T0SZ and T1SZ cover the range translated by
+// the root table, which is 48 bits with the 4 KiB granule and 42 bits with the 64 KiB granule. Walks
+// are inner shareable and write-back cacheable.
+.if _GRANULE_4K
+.equ _BOOT_TSZ, 64 - 48
+.equ _BOOT_TG0, 0b00
+.equ _BOOT_TG1, 0b10
+.else
+.equ _BOOT_TSZ, 64 - 42
+.equ _BOOT_TG0, 0b01
+.equ _BOOT_TG1, 0b11
+.endif
+.equ _BOOT_TCR, _BOOT_TSZ | (0b01 << 8) | (0b01 << 10) | (0b11 << 12) | (_BOOT_TG0 << 14) | (_BOOT_TSZ << 16) | (0b01 << 24) | (0b01 << 26) | (0b11 << 28) | (_BOOT_TG1 << 30)
+
+.equ _EL1, 0x4
 .equ _EL2, 0x8
+.equ _EL3, 0xC
 .equ _core_id_mask, 0b11
 
 //--------------------------------------------------------------------------------------------------
@@ -30,10 +188,15 @@
 // fn _start()
 //------------------------------------------------------------------------------
 _start:
-	// Only proceed if the core executes in EL2. Park it otherwise.
-	mrs	x0, CurrentEL
-	cmp	x0, _EL2
-	b.ne	.L_parking_loop
+	// Remember the device tree blob address passed by the firmware in x0, reported by dtb::addr().
+	mov	x20, x0
+
+	// Remember the exception level the core was started in, reported by entry::boot_el().
+	mrs	x19, CurrentEL
+
+	// Only proceed if the core executes in EL1, EL2 or EL3. Park it otherwise.
+	cmp	x19, _EL1
+	b.lo	.L_parking_loop
 
 	// Only proceed on the boot core. Park it otherwise.
 	mrs	x1, MPIDR_EL1
@@ -44,13 +207,20 @@ _start:
 
 	// If execution reaches here, it is the boot core.
 
+	// Continue in EL2 if started in EL3.
+	LEAVE_EL3
+
 	// Prepare the jump to Rust code.
 .L_prepare_rust:
+	// Enable FP/SIMD before any Rust code runs.
+	ENABLE_FP
+
 	// Set the stack pointer. This ensures that any code in EL2 that needs the stack will work.
-	ADR_REL	x0, __boot_core_stack_end_exclusive
-	mov	sp, x0
+	SET_CORE_STACK
 
-	// Jump to Rust code. x0 holds the function argument provided to _start_rust().
+	// Jump to Rust code. x0, x1 and x2 hold the function arguments provided to _start_rust().
+	lsr	x1, x19, #2
+	mov	x2, x20
 	b	_start_rust
 
 	// Infinitely wait for events (aka "park the core").
@@ -61,3 +231,76 @@ _start:
 .size	_start, . - _start
 .type	_start, function
 .global	_start
+
+//------------------------------------------------------------------------------
+// fn _start_secondary()
+//------------------------------------------------------------------------------
+// Entry point of secondary cores, released from the firmware spin table by `smp::start_core()`.
+_start_secondary:
+	// Only proceed if the core executes in EL1, EL2 or EL3. Park it otherwise.
+	mrs	x0, CurrentEL
+	cmp	x0, _EL1
+	b.lo	.L_parking_loop
+
+	// Continue in EL2 if started in EL3.
+	LEAVE_EL3
+
+	// Enable FP/SIMD before any Rust code runs.
+	ENABLE_FP
+
+	// Set the stack pointer of this core.
+	SET_CORE_STACK
+
+	// Jump to Rust code. x0 holds the function argument provided to _start_rust_secondary().
+	b	_start_rust_secondary
+
+.size	_start_secondary, . - _start_secondary
+.type	_start_secondary, function
+.global	_start_secondary
+
+.if _HIGHER_HALF
+//------------------------------------------------------------------------------
+// fn _start_main_high()
+//------------------------------------------------------------------------------
+// EL1 entry of the boot core with the `higher-half` feature. Executes at the physical load address,
+// switches on the MMU with the boot translation table and continues in `_start_main()` at its link
+// address.
+_start_main_high:
+	POPULATE_BOOT_TABLES
+	ENABLE_BOOT_MMU
+	ENTER_HIGHER_HALF _start_main
+
+.size	_start_main_high, . - _start_main_high
+.type	_start_main_high, function
+.global	_start_main_high
+
+//------------------------------------------------------------------------------
+// fn _start_main_secondary_high()
+//------------------------------------------------------------------------------
+// Same as `_start_main_high()` for secondary cores, which reuse the boot translation table.
+_start_main_secondary_high:
+	ENABLE_BOOT_MMU
+	ENTER_HIGHER_HALF _start_main_secondary
+
+.size	_start_main_secondary_high, . - _start_main_secondary_high
+.type	_start_main_secondary_high, function
+.global	_start_main_secondary_high
+
+//------------------------------------------------------------------------------
+// fn __switch_kernel_table(ttbr1: u64)
+//------------------------------------------------------------------------------
+// Replaces the boot translation table in TTBR1_EL1 of the executing core. The kernel can't execute
+// through TTBR1_EL1 while it is replaced, so this must be called at its physical address, which the
+// boot translation table in TTBR0_EL1 identity maps. Touches neither memory nor the stack.
+__switch_kernel_table:
+	msr	TTBR1_EL1, x0
+	isb
+	tlbi	vmalle1
+	dsb	nsh
+	isb
+	ret
+
+.size	__switch_kernel_table, . - __switch_kernel_table
+.type	__switch_kernel_table, function
+.global	__switch_kernel_table
+.endif
@@ -0,0 +1,228 @@
+//! Data and instruction cache maintenance.
+//!
+//! Range operations work on virtual addresses and affect the caches of all cores in the shareability
+//! domain of the memory. Set/way operations only affect the caches of the executing core.
+//!
+//! # DMA
+//!
+//! DMA masters of the RPi4 are not coherent with the CPU caches. Around a transfer from or to
+//! cacheable memory:
+//!
+//! - Before a device reads memory written by the CPU, such as a transmit buffer, call
+//!   `clean_dcache_range()` to push the data to the point of coherency.
+//! - Before a device writes memory, which the CPU reads afterwards, such as a receive buffer, call
+//!   `clean_invalidate_dcache_range()`, so that no dirty line is evicted over the incoming data.
+//!   After the transfer has completed, call `invalidate_dcache_range()` to discard lines, which were
+//!   speculatively fetched in the meantime.
+//!
+//! Buffers should be aligned to `dcache_line_size()`, because maintenance always affects whole
+//! lines. Memory mapped as `MemAttributes::NonCacheable` needs no maintenance at all, which is the
+//! simpler choice for descriptor rings and other small structures shared with a device.
+//!
+//! # Code
+//!
+//! After writing instructions, such as when loading a program, call `sync_icache_range()` before
+//! executing them.
+
+use core::ops::Range;
+use cortex_a::barrier;
+
+/// Cache maintenance operations by set/way.
+#[derive(Copy, Clone)]
+enum SetWayOp {
+    Clean,
+    Invalidate,
+    CleanInvalidate,
+}
+
+/// Returns the value of the cache type register.
+#[inline(always)]
+fn ctr() -> u64 {
+    let ctr: u64;
+
+    unsafe {
+        asm!("mrs {}, CTR_EL0", out(reg) ctr, options(nomem, nostack, preserves_flags));
+    }
+
+    ctr
+}
+
+/// Returns the smallest data cache line size of all caches in bytes, from CTR_EL0.DminLine.
+pub fn dcache_line_size() -> usize {
+    4 << ((ctr() >> 16) & 0xF)
+}
+
+/// Returns the smallest instruction cache line size of all caches in bytes, from CTR_EL0.IminLine.
+pub fn icache_line_size() -> usize {
+    4 << (ctr() & 0xF)
+}
+
+/// Returns the addresses of all cache lines, which overlap the range.
+fn lines(range: Range<usize>, line_size: usize) -> impl Iterator<Item = usize> {
+    ((range.start & !(line_size - 1))..range.end).step_by(line_size)
+}
+
+/// Cleans the data cache lines of the range to the point of coherency, so that the data is visible
+/// to observers, which don't look up the caches, such as DMA masters.
+pub fn clean_dcache_range(range: Range<usize>) {
+    for addr in lines(range, dcache_line_size()) {
+        unsafe {
+            asm!("dc cvac, {}", in(reg) addr, options(nostack, preserves_flags));
+        }
+    }
+
+    barrier::dsb(barrier::SY);
+}
+
+/// Invalidates the data cache lines of the range to the point of coherency, so that subsequent
+/// reads fetch the data written by observers, which don't look up the caches, such as DMA masters.
+///
+/// # Safety
+///
+/// - Dirty data in the lines is discarded. This includes data outside of the range, which shares a
+///   line with its start or end.
+pub unsafe fn invalidate_dcache_range(range: Range<usize>) {
+    for addr in lines(range, dcache_line_size()) {
+        asm!("dc ivac, {}", in(reg) addr, options(nostack, preserves_flags));
+    }
+
+    barrier::dsb(barrier::SY);
+}
+
+/// Cleans and invalidates the data cache lines of the range to the point of coherency.
+pub fn clean_invalidate_dcache_range(range: Range<usize>) {
+    for addr in lines(range, dcache_line_size()) {
+        unsafe {
+            asm!("dc civac, {}", in(reg) addr, options(nostack, preserves_flags));
+        }
+    }
+
+    barrier::dsb(barrier::SY);
+}
+
+/// Makes instructions written to the range visible to instruction fetches. The data cache is
+/// cleaned to the point of unification, the instruction cache is invalidated and the context of
+/// the executing core is synchronized.
+///
+/// Other cores must execute an `isb` before executing the instructions.
+pub fn sync_icache_range(range: Range<usize>) {
+    for addr in lines(range.clone(), dcache_line_size()) {
+        unsafe {
+            asm!("dc cvau, {}", in(reg) addr, options(nostack, preserves_flags));
+        }
+    }
+
+    barrier::dsb(barrier::ISH);
+
+    for addr in lines(range, icache_line_size()) {
+        unsafe {
+            asm!("ic ivau, {}", in(reg) addr, options(nostack, preserves_flags));
+        }
+    }
+
+    barrier::dsb(barrier::ISH);
+    barrier::isb(barrier::SY);
+}
+
+/// Applies the operation to every line of all data and unified caches of the executing core up to
+/// the level of coherency.
+///
+/// The cache geometry is discovered from CLIDR_EL1 and CCSIDR_EL1, as per ARMv8-A Architecture
+/// Reference Manual section D4.4.8. The CCSIDR_EL1 format without FEAT_CCIDX is assumed.
+fn dcache_all(op: SetWayOp) {
+    let clidr: u64;
+
+    unsafe {
+        asm!("mrs {}, CLIDR_EL1", out(reg) clidr, options(nomem, nostack, preserves_flags));
+    }
+
+    // Level of coherency.
+    let loc = (clidr >> 24) & 0x7;
+
+    for level in 0..loc {
+        // Skip levels without cache or with instruction cache only.
+        let cache_type = (clidr >> (level * 3)) & 0x7;
+        if cache_type < 2 {
+            continue;
+        }
+
+        let ccsidr: u64;
+
+        unsafe {
+            asm!(
+                "msr CSSELR_EL1, {level}",
+                "isb",
+                "mrs {ccsidr}, CCSIDR_EL1",
+                level = in(reg) level << 1,
+                ccsidr = out(reg) ccsidr,
+                options(nomem, nostack, preserves_flags)
+            );
+        }
+
+        let line_shift = (ccsidr & 0x7) + 4;
+        let ways = ((ccsidr >> 3) & 0x3FF) + 1;
+        let sets = ((ccsidr >> 13) & 0x7FFF) + 1;
+
+        // The way is held in the topmost bits of the operand.
+        let way_shift = (ways as u32 - 1).leading_zeros();
+
+        for way in 0..ways {
+            for set in 0..sets {
+                let operand = (way << way_shift) | (set << line_shift) | (level << 1);
+
+                unsafe {
+                    match op {
+                        SetWayOp::Clean => {
+                            asm!("dc csw, {}", in(reg) operand, options(nostack, preserves_flags))
+                        }
+                        SetWayOp::Invalidate => {
+                            asm!("dc isw, {}", in(reg) operand, options(nostack, preserves_flags))
+                        }
+                        SetWayOp::CleanInvalidate => {
+                            asm!("dc cisw, {}", in(reg) operand, options(nostack, preserves_flags))
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    barrier::dsb(barrier::SY);
+    barrier::isb(barrier::SY);
+}
+
+/// Cleans all data and unified caches of the executing core by set/way.
+///
+/// Set/way operations are meant for cache and MMU state transitions of a single core, such as
+/// turning the caches off before handing over to another image. They are no replacement for the
+/// range operations, because lines may migrate between the caches of running cores.
+pub fn clean_dcache_all() {
+    dcache_all(SetWayOp::Clean);
+}
+
+/// Invalidates all data and unified caches of the executing core by set/way, such as before
+/// turning the caches on for the first time.
+///
+/// # Safety
+///
+/// - All dirty data in the caches is discarded.
+pub unsafe fn invalidate_dcache_all() {
+    dcache_all(SetWayOp::Invalidate);
+}
+
+/// Cleans and invalidates all data and unified caches of the executing core by set/way.
+///
+/// See `clean_dcache_all()` for the limitations of set/way operations.
+pub fn clean_invalidate_dcache_all() {
+    dcache_all(SetWayOp::CleanInvalidate);
+}
+
+/// Invalidates all instruction caches of the inner shareable domain to the point of unification.
+pub fn invalidate_icache_all() {
+    unsafe {
+        asm!("ic ialluis", options(nostack, preserves_flags));
+    }
+
+    barrier::dsb(barrier::ISH);
+    barrier::isb(barrier::SY);
+}
@@ -0,0 +1,243 @@
+//! Kernel command line from the `/chosen/bootargs` device tree property.
+//!
+//! The RPi firmware fills `/chosen/bootargs` with the contents of `cmdline.txt` on the boot
+//! partition, prefixed by arguments of its own. Arguments are separated by whitespace and have the
+//! form `key=value` or `flag`. Values containing whitespace can be enclosed in double quotes.
+//!
+//! ```ignore
+//! // cmdline.txt: loglevel=3 baud=115200 test="uart loopback" verbose
+//! let baud: u32 = cmdline::parse("baud").and_then(Result::ok).unwrap_or(115200);
+//! let verbose = cmdline::contains("verbose");
+//! ```
+
+use core::str::FromStr;
+
+#[cfg(target_arch = "aarch64")]
+use crate::dtb;
+
+/// A single command line argument.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Arg<'a> {
+    pub key: &'a str,
+    /// Value after `=` with enclosing quotes removed, `None` for flags.
+    pub value: Option<&'a str>,
+}
+
+/// A parsed command line.
+#[derive(Clone, Copy)]
+pub struct Cmdline<'a> {
+    cmdline: &'a str,
+}
+
+impl<'a> Cmdline<'a> {
+    /// Create a command line from a string.
+    pub const fn new(cmdline: &'a str) -> Self {
+        Self { cmdline }
+    }
+
+    /// The raw command line.
+    pub fn as_str(&self) -> &'a str {
+        self.cmdline
+    }
+
+    /// Iterator over all arguments in order.
+    pub fn args(&self) -> Args<'a> {
+        Args {
+            remaining: self.cmdline,
+        }
+    }
+
+    /// Returns the value of `key`. If the key is given multiple times, the last value wins. Flags
+    /// without a value return an empty string.
+    pub fn get(&self, key: &str) -> Option<&'a str> {
+        self.args()
+            .filter(|arg| arg.key == key)
+            .last()
+            .map(|arg| arg.value.unwrap_or(""))
+    }
+
+    /// Returns true if `key` is given, either as a flag or with a value.
+    pub fn contains(&self, key: &str) -> bool {
+        self.args().any(|arg| arg.key == key)
+    }
+
+    /// Parses the value of `key`. Returns `None` if the key is not given.
+    pub fn parse<T: FromStr>(&self, key: &str) -> Option<Result<T, T::Err>> {
+        self.get(key).map(str::parse)
+    }
+}
+
+/// Iterator over command line arguments.
+#[derive(Clone)]
+pub struct Args<'a> {
+    remaining: &'a str,
+}
+
+impl<'a> Iterator for Args<'a> {
+    type Item = Arg<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let s = self.remaining.trim_start();
+        if s.is_empty() {
+            return None;
+        }
+
+        // The argument ends at the first whitespace outside of double quotes.
+        let mut in_quotes = false;
+        let end = s
+            .char_indices()
+            .find(|&(_, c)| {
+                if c == '"' {
+                    in_quotes = !in_quotes;
+                }
+                c.is_whitespace() && !in_quotes
+            })
+            .map_or(s.len(), |(i, _)| i);
+
+        let (arg, rest) = s.split_at(end);
+        self.remaining = rest;
+
+        Some(match arg.find('=') {
+            Some(i) => {
+                let value = &arg[i + 1..];
+                let value = value
+                    .strip_prefix('"')
+                    .and_then(|v| v.strip_suffix('"'))
+                    .unwrap_or(value);
+
+                Arg {
+                    key: &arg[..i],
+                    value: Some(value),
+                }
+            }
+            None => Arg {
+                key: arg,
+                value: None,
+            },
+        })
+    }
+}
+
+/// Returns the command line passed by the firmware, which is empty if there is no device tree or
+/// no `/chosen/bootargs` property.
+#[cfg(target_arch = "aarch64")]
+pub fn cmdline() -> Cmdline<'static> {
+    Cmdline::new(
+        dtb::fdt()
+            .and_then(|fdt| fdt.chosen_bootargs())
+            .unwrap_or(""),
+    )
+}
+
+/// Returns the value of `key` from the command line, see `Cmdline::get()`.
+#[cfg(target_arch = "aarch64")]
+pub fn get(key: &str) -> Option<&'static str> {
+    cmdline().get(key)
+}
+
+/// Returns true if `key` is given on the command line, see `Cmdline::contains()`.
+#[cfg(target_arch = "aarch64")]
+pub fn contains(key: &str) -> bool {
+    cmdline().contains(key)
+}
+
+/// Parses the value of `key` from the command line, see `Cmdline::parse()`.
+#[cfg(target_arch = "aarch64")]
+pub fn parse<T: FromStr>(key: &str) -> Option<Result<T, T::Err>> {
+    cmdline().parse(key)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(cmdline: &str) -> Vec<Arg<'_>> {
+        Cmdline::new(cmdline).args().collect()
+    }
+
+    fn arg<'a>(key: &'a str, value: Option<&'a str>) -> Arg<'a> {
+        Arg { key, value }
+    }
+
+    #[test]
+    fn whitespace_separates_arguments() {
+        assert_eq!(args(""), []);
+        assert_eq!(args(" \t\n"), []);
+        assert_eq!(
+            args("  loglevel=3\tverbose\n baud=115200 "),
+            [
+                arg("loglevel", Some("3")),
+                arg("verbose", None),
+                arg("baud", Some("115200")),
+            ]
+        );
+    }
+
+    #[test]
+    fn quoted_values() {
+        assert_eq!(
+            args(r#"test="uart loopback" next"#),
+            [arg("test", Some("uart loopback")), arg("next", None)]
+        );
+
+        // Only the first `=` separates the key from the value.
+        assert_eq!(args("root=UUID=1234"), [arg("root", Some("UUID=1234"))]);
+    }
+
+    #[test]
+    fn quotes_not_enclosing_the_value_are_kept() {
+        // Whitespace in quotes does not end the argument, but the quotes are only removed if they
+        // enclose the whole value.
+        assert_eq!(
+            args(r#"key="a b"c next"#),
+            [arg("key", Some(r#""a b"c"#)), arg("next", None)]
+        );
+    }
+
+    #[test]
+    fn unterminated_quote_extends_to_the_end() {
+        assert_eq!(
+            args(r#"first key="a b c"#),
+            [arg("first", None), arg("key", Some(r#""a b c"#))]
+        );
+    }
+
+    #[test]
+    fn empty_value_and_flag() {
+        let cmdline = Cmdline::new(r#"empty= quoted="" flag"#);
+
+        assert_eq!(
+            cmdline.args().collect::<Vec<_>>(),
+            [
+                arg("empty", Some("")),
+                arg("quoted", Some("")),
+                arg("flag", None),
+            ]
+        );
+
+        // Both read as an empty string, only `args()` tells them apart.
+        assert_eq!(cmdline.get("empty"), Some(""));
+        assert_eq!(cmdline.get("quoted"), Some(""));
+        assert_eq!(cmdline.get("flag"), Some(""));
+        assert_eq!(cmdline.get("missing"), None);
+        assert!(cmdline.contains("empty"));
+        assert!(cmdline.contains("flag"));
+        assert!(!cmdline.contains("missing"));
+        assert!(cmdline.parse::<u32>("flag").unwrap().is_err());
+    }
+
+    #[test]
+    fn repeated_keys() {
+        let cmdline = Cmdline::new("baud=9600 loglevel=3 baud=115200");
+
+        assert_eq!(cmdline.get("baud"), Some("115200"));
+        assert_eq!(cmdline.parse::<u32>("baud"), Some(Ok(115200)));
+        assert!(cmdline.contains("baud"));
+        assert_eq!(cmdline.parse::<u32>("missing"), None);
+
+        // A later flag overrides an earlier value.
+        let cmdline = Cmdline::new("verbose=1 verbose");
+        assert_eq!(cmdline.get("verbose"), Some(""));
+        assert!(cmdline.contains("verbose"));
+    }
+}
@@ -0,0 +1,39 @@
+//! Flattened device tree passed by the firmware.
+//!
+//! The RPi firmware loads the device tree blob (DTB) into memory and passes its address in x0 to
+//! the kernel image, which the entry code preserves. The blob must not overlap the kernel image,
+//! `.bss`, heap or stacks, which is ensured by the firmware placing it well above them.
+//!
+//! With the `higher-half` feature, the blob is parsed before the runtime's translation tables are
+//! populated, so it must lie within the first 1 GiB mapped by the boot translation table.
+
+use core::sync::atomic::{AtomicUsize, Ordering};
+use fdt::Fdt;
+
+use crate::memory;
+
+/// Address of the device tree blob, or 0 if none was passed.
+///
+/// Placed in `.data`, because it is written before `.bss` is zeroed.
+#[link_section = ".data.dtb_addr"]
+static DTB_ADDR: AtomicUsize = AtomicUsize::new(0);
+
+/// Records the address passed by the firmware.
+pub(crate) fn set_addr(addr: usize) {
+    DTB_ADDR.store(addr, Ordering::Relaxed);
+}
+
+/// Returns the physical address of the device tree blob passed by the firmware, if any.
+pub fn addr() -> Option<usize> {
+    match DTB_ADDR.load(Ordering::Relaxed) {
+        0 => None,
+        addr => Some(addr),
+    }
+}
+
+/// Returns the parsed device tree passed by the firmware. Returns `None` if there is none, or if
+/// its header is invalid.
+pub fn fdt() -> Option<Fdt<'static>> {
+    // The firmware only passes valid blobs, which stay in place.
+    unsafe { Fdt::from_ptr(memory::phys_to_virt(addr()?) as *const u8).ok() }
+}
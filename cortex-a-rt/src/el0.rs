@@ -0,0 +1,244 @@
+//! Execution of unprivileged (EL0) code.
+//!
+//! EL0 code and its stack must be mapped accessible from EL0. It can request services from the
+//! runtime through the `syscall` module.
+//!
+//! With the `higher-half` feature, each `Task` runs with its own translation table in TTBR0_EL1,
+//! which maps only the task's code, data and stack. Tasks are isolated from the runtime and from
+//! each other, as long as their memory does not share pages. A task executes at the physical
+//! addresses of its memory, which its table identity maps, so it can only use PC-relative
+//! addressing, which is what Rust emits for functions and statics, and must not follow pointers
+//! stored by the runtime. Everything a task touches, including functions it calls, must live in its
+//! code and data ranges, e.g. in page aligned sections of a custom linker script.
+//!
+//! ```ignore
+//! static mut TASK: el0::Task<{ 64 * 1024 }> = el0::Task::new();
+//!
+//! #[link_section = ".task_text"]
+//! extern "C" fn task(arg: u64) -> ! {
+//!     loop {
+//!         unsafe { asm!("svc #0", in("x0") arg) };
+//!     }
+//! }
+//!
+//! // Ranges of the .task_text and .task_data sections, from the linker script.
+//! unsafe {
+//!     TASK.map(task_text_range(), task_data_range())?;
+//!     TASK.run(task, 42)
+//! }
+//! ```
+//!
+//! Without the `higher-half` feature, the default memory layout only grants EL0 access to the
+//! `.el0_text` (read+execute) and `.el0_data` (read+write) linker sections, so tasks placed in them
+//! are isolated from the runtime and the rest of memory, but not from each other. All tasks share
+//! the same two sections, so any task can read and write the data and stacks of every other task.
+//!
+//! ```ignore
+//! #[link_section = ".el0_data"]
+//! static mut TASK_STACK: el0::Stack<4096> = el0::Stack::new();
+//!
+//! #[link_section = ".el0_text"]
+//! extern "C" fn task(arg: u64) -> ! {
+//!     loop {
+//!         unsafe { asm!("svc #0", in("x0") arg) };
+//!     }
+//! }
+//!
+//! unsafe { el0::run_task(task, &mut TASK_STACK, 42) }
+//! ```
+
+#[cfg(feature = "higher-half")]
+use core::{fmt, ops::Range};
+
+use cortex_a::regs::*;
+
+#[cfg(feature = "higher-half")]
+use crate::{
+    memory,
+    mmu::{
+        layout::{task::TaskLayout, VirtualMemoryLayout},
+        mmu,
+        mmu::TaskTranslationTable,
+        Granule, LayoutError,
+    },
+};
+
+/// Entry point of an EL0 task, which receives the argument passed to `run_task()` or `Task::run()`.
+pub type TaskEntry = extern "C" fn(arg: u64) -> !;
+
+/// Stack of an EL0 task.
+///
+/// Without the `higher-half` feature, it must be placed in the `.el0_data` linker section to be
+/// accessible from EL0, which makes it accessible to all other EL0 tasks as well.
+#[repr(C, align(16))]
+pub struct Stack<const SIZE: usize>([u8; SIZE]);
+
+impl<const SIZE: usize> Stack<{ SIZE }> {
+    /// Create a new zeroed stack.
+    pub const fn new() -> Self {
+        Self([0; SIZE])
+    }
+
+    /// Address range of the stack.
+    #[cfg(feature = "higher-half")]
+    fn range(&self) -> Range<usize> {
+        let start = self.0.as_ptr() as usize;
+
+        start..start + SIZE
+    }
+
+    /// Exclusive end address of the stack, which is the initial stack pointer.
+    pub fn end(&self) -> usize {
+        // SIZE is rounded down, so that the end is 16 byte aligned.
+        self.0.as_ptr() as usize + (SIZE & !0xF)
+    }
+}
+
+impl<const SIZE: usize> Default for Stack<{ SIZE }> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Runs the task at EL0 on the executing core, using the given stack.
+///
+/// # Safety
+///
+/// - `entry` must be placed in the `.el0_text` and `stack` in the `.el0_data` linker section, or
+///   otherwise be mapped accessible from EL0.
+/// - The stack must not be used by anything else while the task is running.
+#[cfg(not(feature = "higher-half"))]
+pub unsafe fn run_task<const SIZE: usize>(
+    entry: TaskEntry,
+    stack: &'static mut Stack<{ SIZE }>,
+    arg: u64,
+) -> ! {
+    enter_el0(entry as usize, stack.end(), arg)
+}
+
+/// Task memory mapping errors variants.
+#[cfg(feature = "higher-half")]
+#[derive(Debug)]
+pub enum TaskError {
+    /// The ranges of the task are misaligned or overlap.
+    InvalidLayout(LayoutError),
+    Other(&'static str),
+}
+
+#[cfg(feature = "higher-half")]
+impl fmt::Display for TaskError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TaskError::InvalidLayout(x) => write!(f, "Invalid task memory: {}", x),
+            TaskError::Other(x) => write!(f, "{}", x),
+        }
+    }
+}
+
+/// Stack of an isolated task. Aligned to the largest page size, so that it does not share pages
+/// with other memory.
+#[cfg(feature = "higher-half")]
+#[repr(C, align(65536))]
+struct TaskStack<const SIZE: usize>(Stack<SIZE>);
+
+/// An EL0 task with its own stack and translation table, which isolates it from the runtime and
+/// from other tasks.
+///
+/// Supposed to be a `static`, because the table must stay in place while the task runs. Zeroed, so
+/// that it lands in `.bss`.
+#[cfg(feature = "higher-half")]
+#[repr(C)]
+pub struct Task<const STACK_SIZE: usize> {
+    stack: TaskStack<STACK_SIZE>,
+    table: TaskTranslationTable,
+}
+
+#[cfg(feature = "higher-half")]
+impl<const STACK_SIZE: usize> Task<{ STACK_SIZE }> {
+    /// Create a new task, which is not mapped yet. The stack size must be a multiple of the page
+    /// size.
+    pub const fn new() -> Self {
+        assert!(STACK_SIZE != 0 && STACK_SIZE % Granule::SIZE == 0);
+
+        Self {
+            stack: TaskStack(Stack::new()),
+            table: TaskTranslationTable::new(),
+        }
+    }
+
+    /// Populates the translation table of the task, which maps the code and data ranges and the
+    /// stack of the task, and nothing else.
+    ///
+    /// The ranges are runtime addresses, such as the addresses of linker sections. They must be
+    /// aligned to the page size and must not be shared with the runtime or other tasks. The code is
+    /// read-only and only executable from EL0, the data and the stack are writable.
+    pub fn map(&mut self, code: Range<usize>, data: Range<usize>) -> Result<(), TaskError> {
+        let phys_range = |range: Range<usize>| {
+            if range.is_empty() {
+                0..0
+            } else {
+                memory::virt_to_phys(range.start)..memory::virt_to_phys(range.end)
+            }
+        };
+        let stack = self.stack.0.range();
+
+        let layout = TaskLayout::new(phys_range(code), phys_range(data), phys_range(stack));
+
+        layout.validate().map_err(TaskError::InvalidLayout)?;
+        self.table
+            .populate_tt_entries(&layout)
+            .map_err(TaskError::Other)
+    }
+
+    /// Runs the task at EL0 on the executing core, with its translation table installed in
+    /// TTBR0_EL1.
+    ///
+    /// # Safety
+    ///
+    /// - `entry` must lie in the code range passed to `map()`, which must have succeeded.
+    /// - The task must not run on more than one core at a time.
+    pub unsafe fn run(&'static mut self, entry: TaskEntry, arg: u64) -> ! {
+        assert!(self.table.addr_space_size() != 0, "Task is not mapped");
+
+        mmu().set_user_table(&self.table);
+
+        // The table identity maps the physical addresses of the task.
+        enter_el0(
+            memory::virt_to_phys(entry as usize),
+            memory::virt_to_phys(self.stack.0.end()),
+            arg,
+        )
+    }
+}
+
+#[cfg(feature = "higher-half")]
+impl<const STACK_SIZE: usize> Default for Task<{ STACK_SIZE }> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Drops the executing core into EL0 at `entry`, with the stack pointer set to `stack_end` and
+/// `arg` passed in x0.
+///
+/// Exceptions taken from EL0 use the current EL1 stack, which must therefore remain valid. The
+/// interrupt mask state of the executing core is inherited.
+///
+/// # Safety
+///
+/// - `entry` and `stack_end` must point to memory, which is mapped accessible from EL0.
+/// - `stack_end` must be 16 byte aligned.
+pub unsafe fn enter_el0(entry: usize, stack_end: usize, arg: u64) -> ! {
+    SP_EL0.set(stack_end as u64);
+    ELR_EL1.set(entry as u64);
+
+    // Return to EL0t, the DAIF bits share the same positions in DAIF and SPSR_EL1.
+    SPSR_EL1.set(DAIF.get());
+    SPSR_EL1.modify(SPSR_EL1::M::EL0t);
+
+    asm!(
+        "eret",
+        in("x0") arg,
+        options(noreturn, nostack)
+    )
+}
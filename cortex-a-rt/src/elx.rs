@@ -0,0 +1,53 @@
+//! System registers of the exception level the runtime executes in.
+//!
+//! The runtime executes in EL1, or in EL2 with the `hypervisor` feature. Code that handles
+//! exceptions of the runtime itself uses these accessors instead of the EL1 registers.
+
+/// Reads the exception syndrome register (ESR_EL1 or ESR_EL2).
+#[inline(always)]
+pub(crate) fn esr() -> u64 {
+    let esr: u64;
+
+    unsafe {
+        #[cfg(not(feature = "hypervisor"))]
+        asm!("mrs {}, ESR_EL1", out(reg) esr, options(nomem, nostack, preserves_flags));
+        #[cfg(feature = "hypervisor")]
+        asm!("mrs {}, ESR_EL2", out(reg) esr, options(nomem, nostack, preserves_flags));
+    }
+
+    esr
+}
+
+/// Reads the fault address register (FAR_EL1 or FAR_EL2).
+#[inline(always)]
+pub(crate) fn far() -> u64 {
+    let far: u64;
+
+    unsafe {
+        #[cfg(not(feature = "hypervisor"))]
+        asm!("mrs {}, FAR_EL1", out(reg) far, options(nomem, nostack, preserves_flags));
+        #[cfg(feature = "hypervisor")]
+        asm!("mrs {}, FAR_EL2", out(reg) far, options(nomem, nostack, preserves_flags));
+    }
+
+    far
+}
+
+/// Name suffix of the registers accessed through this module, used for pretty printing.
+#[cfg(not(feature = "hypervisor"))]
+pub(crate) const NAME: &str = "EL1";
+#[cfg(feature = "hypervisor")]
+pub(crate) const NAME: &str = "EL2";
+
+/// Sets the vector base address register (VBAR_EL1 or VBAR_EL2).
+///
+/// # Safety
+///
+/// - `addr` must point to a valid exception vector table.
+#[inline(always)]
+pub(crate) unsafe fn set_vbar(addr: u64) {
+    #[cfg(not(feature = "hypervisor"))]
+    asm!("msr VBAR_EL1, {}", in(reg) addr, options(nomem, nostack, preserves_flags));
+    #[cfg(feature = "hypervisor")]
+    asm!("msr VBAR_EL2, {}", in(reg) addr, options(nomem, nostack, preserves_flags));
+}
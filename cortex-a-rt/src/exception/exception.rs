@@ -6,8 +6,42 @@ use core::{cell::UnsafeCell, fmt};
 use cortex_a::regs::*;
 use register::InMemoryRegister;
 
-// Assembly counterpart to this file.
-global_asm!(include_str!("exception.s"));
+use super::syndrome::{ExceptionClass, Syndrome};
+use crate::{core_id, elx, memory, probe, syscall};
+
+// Assembly counterpart to this file. FP/SIMD registers are only saved if the target has them, the
+// EL2 system registers are used if the runtime executes in EL2.
+#[cfg(target_feature = "neon")]
+macro_rules! fp_context {
+    () => {
+        ".equ _FP_CONTEXT, 1\n"
+    };
+}
+#[cfg(not(target_feature = "neon"))]
+macro_rules! fp_context {
+    () => {
+        ".equ _FP_CONTEXT, 0\n"
+    };
+}
+#[cfg(feature = "hypervisor")]
+macro_rules! hyp {
+    () => {
+        ".equ _HYP, 1\n"
+    };
+}
+#[cfg(not(feature = "hypervisor"))]
+macro_rules! hyp {
+    () => {
+        ".equ _HYP, 0\n"
+    };
+}
+
+global_asm!(concat!(
+    fp_context!(),
+    hyp!(),
+    include_str!(concat!(env!("OUT_DIR"), "/exception_config.s")),
+    include_str!("exception.s")
+));
 
 // Provided by exception.S.
 extern "Rust" {
@@ -18,7 +52,8 @@ pub fn exception_vector_start() -> usize {
     unsafe { __exception_vector_start.get() as usize }
 }
 
-/// Wrapper struct for memory copy of SPSR_EL1.
+/// Wrapper struct for memory copy of SPSR_EL1. Holds SPSR_EL2, which has the same layout, with the
+/// `hypervisor` feature.
 #[repr(transparent)]
 pub struct SpsrEL1(InMemoryRegister<u64, SPSR_EL1::Register>);
 
@@ -31,29 +66,237 @@ pub struct ExceptionContext {
     /// The link register, aka x30.
     lr: u64,
 
-    /// Exception link register. The program counter at the time the exception happened.
+    /// Exception link register. The program counter at the time the exception happened. Holds
+    /// ELR_EL2 with the `hypervisor` feature.
     elr_el1: u64,
 
     /// Saved program status.
     spsr_el1: SpsrEL1,
+
+    /// Stack pointer of the lower exception level, with which an interrupted EL0 task (the guest
+    /// with the `hypervisor` feature) resumes.
+    sp_el0: u64,
+
+    /// Floating-point and SIMD registers, only present on hard-float targets.
+    #[cfg(target_feature = "neon")]
+    fp: FpContext,
+}
+
+/// The floating-point and SIMD state as it is stored on the stack on exception entry.
+#[cfg(target_feature = "neon")]
+#[repr(C)]
+pub struct FpContext {
+    /// Floating-point control register.
+    fpcr: u64,
+
+    /// Floating-point status register.
+    fpsr: u64,
+
+    /// SIMD and floating-point registers.
+    q: [u128; 32],
 }
 
 /// Wrapper struct for pretty printing ESR_EL1.
 pub struct EsrEL1;
 
-/// Prints verbose information about the exception and then panics.
+impl SpsrEL1 {
+    /// Raw value of the saved program status.
+    pub fn get(&self) -> u64 {
+        self.0.get()
+    }
+
+    /// Overwrite the saved program status, which is restored on exception return.
+    pub fn set(&mut self, value: u64) {
+        self.0.set(value)
+    }
+
+    /// Read a field of the saved program status.
+    pub fn read(&self, field: register::Field<u64, SPSR_EL1::Register>) -> u64 {
+        self.0.read(field)
+    }
+
+    /// Modify fields of the saved program status, which are restored on exception return.
+    pub fn modify(&mut self, field: register::FieldValue<u64, SPSR_EL1::Register>) {
+        self.0.modify(field)
+    }
+}
+
+/// Accessors for the saved registers. Modified values are restored on exception return, so
+/// handlers can resume execution with a different state.
+impl ExceptionContext {
+    /// Read general purpose register `x{n}`, where `n` is in range 0..=30.
+    pub fn gpr(&self, n: usize) -> u64 {
+        match n {
+            0..=29 => self.gpr[n],
+            30 => self.lr,
+            _ => panic!("Invalid general purpose register x{}", n),
+        }
+    }
+
+    /// Write general purpose register `x{n}`, where `n` is in range 0..=30.
+    pub fn set_gpr(&mut self, n: usize, value: u64) {
+        match n {
+            0..=29 => self.gpr[n] = value,
+            30 => self.lr = value,
+            _ => panic!("Invalid general purpose register x{}", n),
+        }
+    }
+
+    /// The link register, aka x30.
+    pub fn lr(&self) -> u64 {
+        self.lr
+    }
+
+    /// Set the link register, aka x30.
+    pub fn set_lr(&mut self, value: u64) {
+        self.lr = value;
+    }
+
+    /// The address at which execution resumes on exception return.
+    pub fn elr_el1(&self) -> u64 {
+        self.elr_el1
+    }
+
+    /// Set the address at which execution resumes on exception return.
+    pub fn set_elr_el1(&mut self, value: u64) {
+        self.elr_el1 = value;
+    }
+
+    /// The saved program status.
+    pub fn spsr_el1(&self) -> &SpsrEL1 {
+        &self.spsr_el1
+    }
+
+    /// The saved program status, which is restored on exception return.
+    pub fn spsr_el1_mut(&mut self) -> &mut SpsrEL1 {
+        &mut self.spsr_el1
+    }
+
+    /// The stack pointer of the lower exception level.
+    pub fn sp_el0(&self) -> u64 {
+        self.sp_el0
+    }
+
+    /// Set the stack pointer of the lower exception level, which is restored on exception return.
+    pub fn set_sp_el0(&mut self, value: u64) {
+        self.sp_el0 = value;
+    }
+
+    /// Resume execution after the instruction which caused the synchronous exception, e.g. after
+    /// emulating it.
+    ///
+    /// Must not be used for SVC, HVC and SMC exceptions, where ELR_EL1 already points to the next
+    /// instruction.
+    pub fn skip_instruction(&mut self) {
+        let len = if Syndrome::read().instruction_is_32bit() {
+            4
+        } else {
+            2
+        };
+
+        self.elr_el1 += len;
+    }
+
+    /// The saved floating-point and SIMD state.
+    #[cfg(target_feature = "neon")]
+    pub fn fp(&self) -> &FpContext {
+        &self.fp
+    }
+
+    /// The saved floating-point and SIMD state, which is restored on exception return.
+    #[cfg(target_feature = "neon")]
+    pub fn fp_mut(&mut self) -> &mut FpContext {
+        &mut self.fp
+    }
+}
+
+#[cfg(target_feature = "neon")]
+impl FpContext {
+    /// Read SIMD and floating-point register `q{n}`, where `n` is in range 0..=31.
+    pub fn q(&self, n: usize) -> u128 {
+        self.q[n]
+    }
+
+    /// Write SIMD and floating-point register `q{n}`, where `n` is in range 0..=31.
+    pub fn set_q(&mut self, n: usize, value: u128) {
+        self.q[n] = value;
+    }
+
+    /// Floating-point control register.
+    pub fn fpcr(&self) -> u64 {
+        self.fpcr
+    }
+
+    /// Set the floating-point control register.
+    pub fn set_fpcr(&mut self, value: u64) {
+        self.fpcr = value;
+    }
+
+    /// Floating-point status register.
+    pub fn fpsr(&self) -> u64 {
+        self.fpsr
+    }
+
+    /// Set the floating-point status register.
+    pub fn set_fpsr(&mut self, value: u64) {
+        self.fpsr = value;
+    }
+}
+
+/// Recovers faults of probing accesses from the `probe` module. Otherwise, prints verbose
+/// information about the exception and then panics.
 pub fn default_exception_handler(e: &mut ExceptionContext) {
+    // Faults of probing accesses are recovered by resuming at their landing pad.
+    if probe::fixup_exception(e) {
+        return;
+    }
+
+    if let Some(core) = stack_overflow_core() {
+        stack_overflow_handler(core, e);
+    }
+
     panic!(
         "\n\nCPU Exception!\n\
-         FAR_EL1: {:#018x}\n\
+         FAR_{}: {:#018x}\n\
          {}\n\
          {}",
-        FAR_EL1.get(),
+        elx::NAME,
+        elx::far(),
         EsrEL1 {},
         e
     );
 }
 
+/// Returns the core whose stack has overflowed, if the current exception is a data abort on a
+/// stack guard page.
+fn stack_overflow_core() -> Option<usize> {
+    match Syndrome::read().exception_class() {
+        ExceptionClass::DataAbortCurrentEL => memory::stack_guard_owner(elx::far() as usize),
+        _ => None,
+    }
+}
+
+/// Reports a stack overflow and panics.
+fn stack_overflow_handler(core: usize, e: &mut ExceptionContext) -> ! {
+    panic!(
+        "\n\nStack overflow on core {}!\n\
+         FAR_{}: {:#018x}\n\
+         {}",
+        core,
+        elx::NAME,
+        elx::far(),
+        e
+    );
+}
+
+/// Called on the emergency stack when the exception context did not fit on the faulting stack.
+#[no_mangle]
+unsafe extern "C" fn current_elx_stack_overflow(e: &mut ExceptionContext) {
+    let core = stack_overflow_core().unwrap_or(core_id() as usize);
+
+    stack_overflow_handler(core, e);
+}
+
 //------------------------------------------------------------------------------
 // Current, EL0
 //------------------------------------------------------------------------------
@@ -102,10 +345,12 @@ unsafe extern "C" fn current_elx_serror(e: &mut ExceptionContext) {
 // Lower, AArch64
 //------------------------------------------------------------------------------
 
+/// Dispatches syscalls of EL0 code, or hypercalls of the guest with the `hypervisor` feature, and
+/// passes other exceptions to the task fault handler, see the `syscall` module.
 #[no_mangle]
 #[linkage = "weak"]
 unsafe extern "C" fn lower_aarch64_synchronous(e: &mut ExceptionContext) {
-    default_exception_handler(e);
+    syscall::dispatch(e);
 }
 
 #[no_mangle]
@@ -150,23 +395,21 @@ unsafe extern "C" fn lower_aarch32_serror(e: &mut ExceptionContext) {
 #[rustfmt::skip]
 impl fmt::Display for EsrEL1 {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        let esr_el1 = ESR_EL1.extract();
+        let syndrome = Syndrome::read();
+        let ec = syndrome.exception_class();
 
         // Raw print of whole register.
-        writeln!(f, "ESR_EL1: {:#010x}", esr_el1.get())?;
-
-        // Raw print of exception class.
-        write!(f, "      Exception Class         (EC) : {:#x}", esr_el1.read(ESR_EL1::EC))?;
+        writeln!(f, "ESR_{}: {:#010x}", elx::NAME, syndrome.raw())?;
 
-        // Exception class, translation.
-        let ec_translation = match esr_el1.read_as_enum(ESR_EL1::EC) {
-            Some(ESR_EL1::EC::Value::DataAbortCurrentEL) => "Data Abort, current EL",
-            _ => "N/A",
-        };
-        writeln!(f, " - {}", ec_translation)?;
+        // Raw print of exception class and its translation.
+        let ec_raw = syndrome.raw() >> 26;
+        writeln!(f, "      Exception Class         (EC) : {:#x} - {}", ec_raw, ec)?;
 
         // Raw print of instruction specific syndrome.
-        write!(f, "      Instr Specific Syndrome (ISS): {:#x}", esr_el1.read(ESR_EL1::ISS))
+        writeln!(f, "      Instr Specific Syndrome (ISS): {:#x}", syndrome.iss())?;
+
+        // Decoded instruction specific syndrome.
+        write!(f, "{}", syndrome.decode())
     }
 }
 
@@ -220,7 +463,33 @@ impl fmt::Display for ExceptionContext {
         for (i, reg) in self.gpr.iter().enumerate() {
             write!(f, "      x{: <2}: {: >#018x}{}", i, reg, alternating(i))?;
         }
-        write!(f, "      lr : {:#018x}", self.lr)
+        write!(f, "      lr : {:#018x}", self.lr)?;
+
+        #[cfg(target_feature = "neon")]
+        write!(f, "\n{}", self.fp)?;
+
+        Ok(())
+    }
+}
+
+/// Human readable print of the floating-point and SIMD state.
+#[cfg(target_feature = "neon")]
+impl fmt::Display for FpContext {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "FPCR: {:#010x}", self.fpcr)?;
+        writeln!(f, "FPSR: {:#010x}", self.fpsr)?;
+        writeln!(f)?;
+        writeln!(f, "SIMD and floating-point registers:")?;
+
+        for (i, reg) in self.q.iter().enumerate() {
+            write!(f, "      q{: <2}: {:#034x}", i, reg)?;
+
+            if i + 1 < self.q.len() {
+                writeln!(f)?;
+            }
+        }
+
+        Ok(())
     }
 }
 
@@ -6,45 +6,79 @@
 // Definitions
 //--------------------------------------------------------------------------------------------------
 
+// `_FP_CONTEXT` is defined by exception.rs and is set when the target has FP/SIMD registers.
+// `_HYP` is set when the runtime executes in EL2 (`hypervisor` feature). `_emergency_stack_shift`
+// is generated by build.rs.
+//
+// Layout of the exception context, must match `ExceptionContext` in exception.rs.
+.equ _GPR_CONTEXT_SIZE, 16 * 17
+.equ _FP_CONTEXT_OFFSET, _GPR_CONTEXT_SIZE
+.if _FP_CONTEXT
+.equ _CONTEXT_SIZE, _GPR_CONTEXT_SIZE + 16 * 33
+.else
+.equ _CONTEXT_SIZE, _GPR_CONTEXT_SIZE
+.endif
+
+/// Read the system register `\sysreg` of the exception level the runtime executes in.
+.macro MRS_ELX register, sysreg
+.if _HYP
+	mrs	\register, \sysreg\()_EL2
+.else
+	mrs	\register, \sysreg\()_EL1
+.endif
+.endm
+
+/// Write the system register `\sysreg` of the exception level the runtime executes in.
+.macro MSR_ELX sysreg, register
+.if _HYP
+	msr	\sysreg\()_EL2, \register
+.else
+	msr	\sysreg\()_EL1, \register
+.endif
+.endm
+
 /// Call the function provided by parameter `\handler` after saving the exception context. Provide
 /// the context as the first parameter to '\handler'.
+///
+/// The vector table only has room for 32 instructions per entry, so the bulk of the context is
+/// saved by `__exception_save_context`.
 .macro CALL_WITH_CONTEXT handler
 	// Make room on the stack for the exception context.
-	sub	sp,  sp,  #16 * 17
+	sub	sp,  sp,  #_CONTEXT_SIZE
 
-	// Store all general purpose registers on the stack.
+	// Free up x0 and x1 for passing the handler address.
 	stp	x0,  x1,  [sp, #16 * 0]
-	stp	x2,  x3,  [sp, #16 * 1]
-	stp	x4,  x5,  [sp, #16 * 2]
-	stp	x6,  x7,  [sp, #16 * 3]
-	stp	x8,  x9,  [sp, #16 * 4]
-	stp	x10, x11, [sp, #16 * 5]
-	stp	x12, x13, [sp, #16 * 6]
-	stp	x14, x15, [sp, #16 * 7]
-	stp	x16, x17, [sp, #16 * 8]
-	stp	x18, x19, [sp, #16 * 9]
-	stp	x20, x21, [sp, #16 * 10]
-	stp	x22, x23, [sp, #16 * 11]
-	stp	x24, x25, [sp, #16 * 12]
-	stp	x26, x27, [sp, #16 * 13]
-	stp	x28, x29, [sp, #16 * 14]
 
-	// Add the exception link register (ELR_EL1) and the saved program status (SPSR_EL1).
-	mrs	x1,  ELR_EL1
-	mrs	x2,  SPSR_EL1
+	adrp	x1,  \handler
+	add	x1,  x1,  #:lo12:\handler
 
-	stp	lr,  x1,  [sp, #16 * 15]
-	str	x2,       [sp, #16 * 16]
+	b	__exception_save_context
+.endm
 
-	// x0 is the first argument for the function called through `\handler`.
-	mov	x0,  sp
+/// Call the function provided by parameter `\handler` after saving the exception context, same as
+/// `CALL_WITH_CONTEXT`, but first check whether the exception context fits on the current stack.
+/// If it does not, the stack has overflowed into its guard page and the context is saved on the
+/// emergency stack of the executing core instead.
+///
+/// TPIDR_EL1 (TPIDR_EL2 with `_HYP`) is used as a scratch register and PAR_EL1 is clobbered. Unlike
+/// TPIDRRO_EL0 or SP_EL0, TPIDR_ELx is neither visible to EL0 nor part of the saved context.
+.macro CALL_WITH_CONTEXT_STACK_CHECKED handler
+	MSR_ELX	TPIDR, x0
 
-	// Call `\handler`.
-	bl	\handler
+	// Probe whether the lowest address of the exception context is writable.
+	sub	x0,  sp,  #_CONTEXT_SIZE
+.if _HYP
+	at	s1e2w, x0
+.else
+	at	s1e1w, x0
+.endif
+	isb
+	mrs	x0,  PAR_EL1
+	tbnz	x0,  #0, __exception_stack_overflow
 
-	// After returning from exception handling code, replay the saved context and return via
-	// `eret`.
-	b	__exception_restore_context
+	MRS_ELX	x0,  TPIDR
+
+	CALL_WITH_CONTEXT \handler
 .endm
 
 .macro FIQ_SUSPEND
@@ -85,7 +119,7 @@ __exception_vector_start:
 
 // Current exception level with SP_ELx, x > 0.
 .org 0x200
-	CALL_WITH_CONTEXT current_elx_synchronous
+	CALL_WITH_CONTEXT_STACK_CHECKED current_elx_synchronous
 .org 0x280
 	CALL_WITH_CONTEXT current_elx_irq
 .org 0x300
@@ -114,15 +148,135 @@ __exception_vector_start:
 	CALL_WITH_CONTEXT lower_aarch32_serror
 .org 0x800
 
+//------------------------------------------------------------------------------
+// fn __exception_stack_overflow()
+//------------------------------------------------------------------------------
+// The original x0 is stored in TPIDR_ELx. `_emergency_stack_shift` is defined by build.rs.
+
+__exception_stack_overflow:
+	// The faulting stack is unusable, switch to the emergency stack of the executing core, which
+	// grows down from `__emergency_stacks_start + (N + 1) * __emergency_stack_size`. Only x0 and
+	// sp are available.
+	mrs	x0,  MPIDR_EL1
+	and	x0,  x0,  #0b11		// Core ID mask
+	add	x0,  x0,  #1
+	lsl	x0,  x0,  _emergency_stack_shift
+	mov	sp,  x0
+	ldr	x0,  =__emergency_stacks_start
+	add	sp,  sp,  x0
+
+	MRS_ELX	x0,  TPIDR
+
+	CALL_WITH_CONTEXT current_elx_stack_overflow
+
+.size	__exception_stack_overflow, . - __exception_stack_overflow
+.type	__exception_stack_overflow, function
+
+//------------------------------------------------------------------------------
+// fn __exception_save_context()
+//------------------------------------------------------------------------------
+// Continuation of `CALL_WITH_CONTEXT`. x0 and x1 are already saved and x1 holds the address of the
+// handler.
+__exception_save_context:
+	// Store the remaining general purpose registers on the stack.
+	stp	x2,  x3,  [sp, #16 * 1]
+	stp	x4,  x5,  [sp, #16 * 2]
+	stp	x6,  x7,  [sp, #16 * 3]
+	stp	x8,  x9,  [sp, #16 * 4]
+	stp	x10, x11, [sp, #16 * 5]
+	stp	x12, x13, [sp, #16 * 6]
+	stp	x14, x15, [sp, #16 * 7]
+	stp	x16, x17, [sp, #16 * 8]
+	stp	x18, x19, [sp, #16 * 9]
+	stp	x20, x21, [sp, #16 * 10]
+	stp	x22, x23, [sp, #16 * 11]
+	stp	x24, x25, [sp, #16 * 12]
+	stp	x26, x27, [sp, #16 * 13]
+	stp	x28, x29, [sp, #16 * 14]
+
+	// Add the exception link register (ELR_ELx), the saved program status (SPSR_ELx) and the stack
+	// pointer of the lower exception level (SP_EL0).
+	MRS_ELX	x2,  ELR
+	MRS_ELX	x3,  SPSR
+	mrs	x4,  SP_EL0
+
+	stp	lr,  x2,  [sp, #16 * 15]
+	stp	x3,  x4,  [sp, #16 * 16]
+
+.if _FP_CONTEXT
+	// Add the floating-point control and status registers, followed by the SIMD registers.
+	add	x2,  sp,  #_FP_CONTEXT_OFFSET
+
+	mrs	x3,  FPCR
+	mrs	x4,  FPSR
+	stp	x3,  x4,  [x2], #16
+
+	stp	q0,  q1,  [x2, #32 * 0]
+	stp	q2,  q3,  [x2, #32 * 1]
+	stp	q4,  q5,  [x2, #32 * 2]
+	stp	q6,  q7,  [x2, #32 * 3]
+	stp	q8,  q9,  [x2, #32 * 4]
+	stp	q10, q11, [x2, #32 * 5]
+	stp	q12, q13, [x2, #32 * 6]
+	stp	q14, q15, [x2, #32 * 7]
+	stp	q16, q17, [x2, #32 * 8]
+	stp	q18, q19, [x2, #32 * 9]
+	stp	q20, q21, [x2, #32 * 10]
+	stp	q22, q23, [x2, #32 * 11]
+	stp	q24, q25, [x2, #32 * 12]
+	stp	q26, q27, [x2, #32 * 13]
+	stp	q28, q29, [x2, #32 * 14]
+	stp	q30, q31, [x2, #32 * 15]
+.endif
+
+	// x0 is the first argument for the function called through the handler.
+	mov	x0,  sp
+
+	// Call the handler.
+	blr	x1
+
+	// After returning from exception handling code, replay the saved context and return via
+	// `eret`.
+	b	__exception_restore_context
+
+.size	__exception_save_context, . - __exception_save_context
+.type	__exception_save_context, function
+
 //------------------------------------------------------------------------------
 // fn __exception_restore_context()
 //------------------------------------------------------------------------------
 __exception_restore_context:
-	ldr	w19,      [sp, #16 * 16]
+.if _FP_CONTEXT
+	add	x2,  sp,  #_FP_CONTEXT_OFFSET
+
+	ldp	x3,  x4,  [x2], #16
+	msr	FPCR, x3
+	msr	FPSR, x4
+
+	ldp	q0,  q1,  [x2, #32 * 0]
+	ldp	q2,  q3,  [x2, #32 * 1]
+	ldp	q4,  q5,  [x2, #32 * 2]
+	ldp	q6,  q7,  [x2, #32 * 3]
+	ldp	q8,  q9,  [x2, #32 * 4]
+	ldp	q10, q11, [x2, #32 * 5]
+	ldp	q12, q13, [x2, #32 * 6]
+	ldp	q14, q15, [x2, #32 * 7]
+	ldp	q16, q17, [x2, #32 * 8]
+	ldp	q18, q19, [x2, #32 * 9]
+	ldp	q20, q21, [x2, #32 * 10]
+	ldp	q22, q23, [x2, #32 * 11]
+	ldp	q24, q25, [x2, #32 * 12]
+	ldp	q26, q27, [x2, #32 * 13]
+	ldp	q28, q29, [x2, #32 * 14]
+	ldp	q30, q31, [x2, #32 * 15]
+.endif
+
+	ldp	x19, x21, [sp, #16 * 16]
 	ldp	lr,  x20, [sp, #16 * 15]
 
-	msr	SPSR_EL1, x19
-	msr	ELR_EL1,  x20
+	MSR_ELX	SPSR, x19
+	MSR_ELX	ELR,  x20
+	msr	SP_EL0, x21
 
 	ldp	x0,  x1,  [sp, #16 * 0]
 	ldp	x2,  x3,  [sp, #16 * 1]
@@ -140,7 +294,7 @@ __exception_restore_context:
 	ldp	x26, x27, [sp, #16 * 13]
 	ldp	x28, x29, [sp, #16 * 14]
 
-	add	sp,  sp,  #16 * 17
+	add	sp,  sp,  #_CONTEXT_SIZE
 
 	eret
 
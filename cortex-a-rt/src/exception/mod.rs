@@ -1,9 +1,17 @@
-use cortex_a::{barrier, regs::*};
+#[cfg(target_arch = "aarch64")]
+use cortex_a::barrier;
 
+#[cfg(target_arch = "aarch64")]
+use crate::elx;
+
+#[cfg(target_arch = "aarch64")]
 pub mod exception;
+#[cfg(target_arch = "aarch64")]
 pub mod masking;
+pub mod syndrome;
 
-/// Init exception handling by setting the exception vector base address register.
+/// Init exception handling by setting the exception vector base address register of the exception
+/// level the runtime executes in.
 ///
 /// # Safety
 ///
@@ -11,8 +19,9 @@ pub mod masking;
 /// - The vector table and the symbol `__exception_vector_table_start` from the linker script must
 ///   adhere to the alignment and size constraints demanded by the ARMv8-A Architecture Reference
 ///   Manual.
+#[cfg(target_arch = "aarch64")]
 pub unsafe fn handling_init() {
-    VBAR_EL1.set(exception::exception_vector_start() as u64);
+    elx::set_vbar(exception::exception_vector_start() as u64);
 
     // Force VBAR update to complete before next instruction.
     barrier::isb(barrier::SY);
@@ -0,0 +1,845 @@
+//! Exception syndrome (ESR_EL1) decoding.
+//!
+//! The syndrome consists of the exception class (EC), which determines the layout of the
+//! instruction specific syndrome (ISS). Decoded values are usable both for printing and for
+//! programmatic handling of exceptions.
+//!
+//! # Resources
+//!
+//! - ARMv8-A Architecture Reference Manual, section D13.2.37 "ESR_EL1, Exception Syndrome Register"
+
+use core::fmt;
+use register::{register_bitfields, LocalRegisterCopy};
+
+#[cfg(target_arch = "aarch64")]
+use crate::elx;
+
+// Generic ESR layout.
+register_bitfields! {u64,
+    ESR [
+        /// Exception class.
+        EC  OFFSET(26) NUMBITS(6) [],
+
+        /// Instruction length for synchronous exceptions.
+        IL  OFFSET(25) NUMBITS(1) [
+            Trapped16Bit = 0,
+            Trapped32Bit = 1
+        ],
+
+        /// Instruction specific syndrome.
+        ISS OFFSET(0) NUMBITS(25) []
+    ]
+}
+
+// ISS layouts for the individual exception classes.
+register_bitfields! {u32,
+    /// ISS of data aborts and watchpoints.
+    ISS_DATA_ABORT [
+        /// Instruction syndrome valid, i.e. bits [23:14] hold valid information.
+        ISV   OFFSET(24) NUMBITS(1) [],
+
+        /// Syndrome access size.
+        SAS   OFFSET(22) NUMBITS(2) [
+            Byte = 0b00,
+            Halfword = 0b01,
+            Word = 0b10,
+            Doubleword = 0b11
+        ],
+
+        /// Syndrome sign extend.
+        SSE   OFFSET(21) NUMBITS(1) [],
+
+        /// Syndrome register transfer, the register number of the faulting access.
+        SRT   OFFSET(16) NUMBITS(5) [],
+
+        /// Sixty four bit register.
+        SF    OFFSET(15) NUMBITS(1) [],
+
+        /// Acquire/Release semantics.
+        AR    OFFSET(14) NUMBITS(1) [],
+
+        /// FAR not valid.
+        FnV   OFFSET(10) NUMBITS(1) [],
+
+        /// External abort type.
+        EA    OFFSET(9) NUMBITS(1) [],
+
+        /// Cache maintenance.
+        CM    OFFSET(8) NUMBITS(1) [],
+
+        /// Stage 2 fault during a stage 1 translation table walk.
+        S1PTW OFFSET(7) NUMBITS(1) [],
+
+        /// Write not Read.
+        WnR   OFFSET(6) NUMBITS(1) [],
+
+        /// Data fault status code.
+        DFSC  OFFSET(0) NUMBITS(6) []
+    ],
+
+    /// ISS of instruction aborts.
+    ISS_INSTRUCTION_ABORT [
+        /// FAR not valid.
+        FnV   OFFSET(10) NUMBITS(1) [],
+
+        /// External abort type.
+        EA    OFFSET(9) NUMBITS(1) [],
+
+        /// Stage 2 fault during a stage 1 translation table walk.
+        S1PTW OFFSET(7) NUMBITS(1) [],
+
+        /// Instruction fault status code.
+        IFSC  OFFSET(0) NUMBITS(6) []
+    ],
+
+    /// ISS of SVC, HVC, SMC and BRK instructions.
+    ISS_IMMEDIATE [
+        /// The immediate value of the instruction.
+        IMM16 OFFSET(0) NUMBITS(16) []
+    ],
+
+    /// ISS of trapped MSR, MRS and System instructions.
+    ISS_SYS_REG [
+        Op0       OFFSET(20) NUMBITS(2) [],
+        Op2       OFFSET(17) NUMBITS(3) [],
+        Op1       OFFSET(14) NUMBITS(3) [],
+        CRn       OFFSET(10) NUMBITS(4) [],
+
+        /// The general purpose register used for the transfer.
+        Rt        OFFSET(5) NUMBITS(5) [],
+
+        CRm       OFFSET(1) NUMBITS(4) [],
+
+        /// Direction of the access.
+        Direction OFFSET(0) NUMBITS(1) [
+            Write = 0,
+            Read = 1
+        ]
+    ],
+
+    /// ISS of software step exceptions.
+    ISS_SOFTWARE_STEP [
+        /// Instruction syndrome valid, i.e. EX holds valid information.
+        ISV  OFFSET(24) NUMBITS(1) [],
+
+        /// Exclusive operation, the stepped instruction was a load-exclusive.
+        EX   OFFSET(6) NUMBITS(1) [],
+
+        /// Instruction fault status code.
+        IFSC OFFSET(0) NUMBITS(6) []
+    ]
+}
+
+/// Exception class, as per ARMv8-A Architecture Reference Manual, ESR_ELx.EC.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExceptionClass {
+    Unknown,
+    TrappedWfiWfe,
+    TrappedMcrMrcCp15,
+    TrappedMcrrMrrcCp15,
+    TrappedMcrMrcCp14,
+    TrappedLdcStc,
+    TrappedFpSimd,
+    TrappedVmrs,
+    TrappedPointerAuth,
+    TrappedMrrcCp14,
+    BranchTarget,
+    IllegalExecutionState,
+    SvcAArch32,
+    HvcAArch32,
+    SmcAArch32,
+    SvcAArch64,
+    HvcAArch64,
+    SmcAArch64,
+    TrappedMsrMrs,
+    TrappedSve,
+    TrappedEret,
+    PointerAuthFailure,
+    ImplementationDefinedEL3,
+    InstructionAbortLowerEL,
+    InstructionAbortCurrentEL,
+    PcAlignmentFault,
+    DataAbortLowerEL,
+    DataAbortCurrentEL,
+    SpAlignmentFault,
+    TrappedFpAArch32,
+    TrappedFpAArch64,
+    SError,
+    BreakpointLowerEL,
+    BreakpointCurrentEL,
+    SoftwareStepLowerEL,
+    SoftwareStepCurrentEL,
+    WatchpointLowerEL,
+    WatchpointCurrentEL,
+    BkptAArch32,
+    VectorCatchAArch32,
+    BrkAArch64,
+    /// Exception class, which is reserved or not defined by ARMv8-A.
+    Reserved(u8),
+}
+
+/// Size of the faulting data access.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AccessSize {
+    Byte,
+    Halfword,
+    Word,
+    Doubleword,
+}
+
+/// Decoded data or instruction fault status code (DFSC/IFSC).
+///
+/// Variants with a level hold the translation table level at which the fault occurred.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FaultStatus {
+    AddressSize(u8),
+    Translation(u8),
+    AccessFlag(u8),
+    Permission(u8),
+    SyncExternal,
+    SyncTagCheck,
+    SyncExternalOnWalk(u8),
+    SyncParity,
+    SyncParityOnWalk(u8),
+    Alignment,
+    Debug,
+    TlbConflict,
+    UnsupportedAtomicUpdate,
+    Lockdown,
+    UnsupportedExclusive,
+    /// Fault status code, which is reserved or not defined by ARMv8-A.
+    Other(u8),
+}
+
+/// Register transfer details of a data abort, valid if the abort was caused by a single general
+/// purpose register load or store.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InstructionSyndrome {
+    /// Size of the access.
+    pub access_size: AccessSize,
+
+    /// Loaded value is sign extended.
+    pub sign_extend: bool,
+
+    /// Register number of the transfer.
+    pub register: u8,
+
+    /// The transfer register is 64 bit wide, otherwise 32 bit.
+    pub sixty_four_bit: bool,
+
+    /// The instruction has acquire/release semantics.
+    pub acquire_release: bool,
+}
+
+/// Decoded ISS of a data abort.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DataAbort {
+    /// Register transfer details, if valid.
+    pub syndrome: Option<InstructionSyndrome>,
+
+    /// FAR_EL1 does not hold the faulting address.
+    pub far_not_valid: bool,
+
+    /// External abort type, implementation defined.
+    pub external_abort: bool,
+
+    /// The abort was caused by a cache maintenance or address translation instruction.
+    pub cache_maintenance: bool,
+
+    /// Stage 2 fault during a stage 1 translation table walk.
+    pub s1ptw: bool,
+
+    /// The abort was caused by a write, otherwise by a read.
+    pub write: bool,
+
+    /// Data fault status code.
+    pub status: FaultStatus,
+}
+
+/// Decoded ISS of an instruction abort.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InstructionAbort {
+    /// FAR_EL1 does not hold the faulting address.
+    pub far_not_valid: bool,
+
+    /// External abort type, implementation defined.
+    pub external_abort: bool,
+
+    /// Stage 2 fault during a stage 1 translation table walk.
+    pub s1ptw: bool,
+
+    /// Instruction fault status code.
+    pub status: FaultStatus,
+}
+
+/// Direction of a trapped system register access.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SysRegDirection {
+    /// MSR or System instruction.
+    Write,
+
+    /// MRS or System instruction with result.
+    Read,
+}
+
+/// Decoded ISS of a trapped MSR, MRS or System instruction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SysRegTrap {
+    pub op0: u8,
+    pub op1: u8,
+    pub crn: u8,
+    pub crm: u8,
+    pub op2: u8,
+
+    /// The general purpose register used for the transfer.
+    pub register: u8,
+
+    pub direction: SysRegDirection,
+}
+
+/// Decoded ISS of a watchpoint.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Watchpoint {
+    /// The watchpoint was hit by a cache maintenance instruction.
+    pub cache_maintenance: bool,
+
+    /// The watchpoint was hit by a write, otherwise by a read.
+    pub write: bool,
+}
+
+/// Decoded ISS of a software step exception.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SoftwareStep {
+    /// The stepped instruction was a load-exclusive, if known.
+    pub exclusive: Option<bool>,
+}
+
+/// Instruction specific syndrome, decoded according to the exception class.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Iss {
+    DataAbort(DataAbort),
+    InstructionAbort(InstructionAbort),
+
+    /// Immediate of the SVC instruction.
+    Svc(u16),
+
+    /// Immediate of the HVC instruction.
+    Hvc(u16),
+
+    /// Immediate of the SMC instruction.
+    Smc(u16),
+
+    /// Comment of the BRK instruction.
+    Brk(u16),
+
+    SysRegTrap(SysRegTrap),
+
+    /// Hardware breakpoint.
+    Breakpoint,
+
+    Watchpoint(Watchpoint),
+    SoftwareStep(SoftwareStep),
+
+    /// ISS, which is not decoded for this exception class.
+    Raw(u32),
+}
+
+/// A copy of the exception syndrome register.
+#[derive(Clone, Copy)]
+pub struct Syndrome(LocalRegisterCopy<u64, ESR::Register>);
+
+impl From<u8> for ExceptionClass {
+    fn from(ec: u8) -> Self {
+        use ExceptionClass::*;
+
+        match ec {
+            0x00 => Unknown,
+            0x01 => TrappedWfiWfe,
+            0x03 => TrappedMcrMrcCp15,
+            0x04 => TrappedMcrrMrrcCp15,
+            0x05 => TrappedMcrMrcCp14,
+            0x06 => TrappedLdcStc,
+            0x07 => TrappedFpSimd,
+            0x08 => TrappedVmrs,
+            0x09 => TrappedPointerAuth,
+            0x0C => TrappedMrrcCp14,
+            0x0D => BranchTarget,
+            0x0E => IllegalExecutionState,
+            0x11 => SvcAArch32,
+            0x12 => HvcAArch32,
+            0x13 => SmcAArch32,
+            0x15 => SvcAArch64,
+            0x16 => HvcAArch64,
+            0x17 => SmcAArch64,
+            0x18 => TrappedMsrMrs,
+            0x19 => TrappedSve,
+            0x1A => TrappedEret,
+            0x1C => PointerAuthFailure,
+            0x1F => ImplementationDefinedEL3,
+            0x20 => InstructionAbortLowerEL,
+            0x21 => InstructionAbortCurrentEL,
+            0x22 => PcAlignmentFault,
+            0x24 => DataAbortLowerEL,
+            0x25 => DataAbortCurrentEL,
+            0x26 => SpAlignmentFault,
+            0x28 => TrappedFpAArch32,
+            0x2C => TrappedFpAArch64,
+            0x2F => SError,
+            0x30 => BreakpointLowerEL,
+            0x31 => BreakpointCurrentEL,
+            0x32 => SoftwareStepLowerEL,
+            0x33 => SoftwareStepCurrentEL,
+            0x34 => WatchpointLowerEL,
+            0x35 => WatchpointCurrentEL,
+            0x38 => BkptAArch32,
+            0x3A => VectorCatchAArch32,
+            0x3C => BrkAArch64,
+            ec => Reserved(ec),
+        }
+    }
+}
+
+impl From<u8> for FaultStatus {
+    fn from(fsc: u8) -> Self {
+        use FaultStatus::*;
+
+        let level = fsc & 0b11;
+
+        match fsc {
+            0b00_0000..=0b00_0011 => AddressSize(level),
+            0b00_0100..=0b00_0111 => Translation(level),
+            0b00_1000..=0b00_1011 => AccessFlag(level),
+            0b00_1100..=0b00_1111 => Permission(level),
+            0b01_0000 => SyncExternal,
+            0b01_0001 => SyncTagCheck,
+            0b01_0100..=0b01_0111 => SyncExternalOnWalk(level),
+            0b01_1000 => SyncParity,
+            0b01_1100..=0b01_1111 => SyncParityOnWalk(level),
+            0b10_0001 => Alignment,
+            0b10_0010 => Debug,
+            0b11_0000 => TlbConflict,
+            0b11_0001 => UnsupportedAtomicUpdate,
+            0b11_0100 => Lockdown,
+            0b11_0101 => UnsupportedExclusive,
+            fsc => Other(fsc),
+        }
+    }
+}
+
+impl ExceptionClass {
+    /// Returns true for data and instruction aborts.
+    pub fn is_abort(&self) -> bool {
+        matches!(
+            self,
+            ExceptionClass::InstructionAbortLowerEL
+                | ExceptionClass::InstructionAbortCurrentEL
+                | ExceptionClass::DataAbortLowerEL
+                | ExceptionClass::DataAbortCurrentEL
+        )
+    }
+}
+
+impl Syndrome {
+    /// Reads the syndrome of the exception currently being handled. This is ESR_EL2 with the
+    /// `hypervisor` feature.
+    #[cfg(target_arch = "aarch64")]
+    pub fn read() -> Self {
+        Self::from_raw(elx::esr())
+    }
+
+    /// Creates a syndrome from a raw ESR value.
+    pub fn from_raw(esr: u64) -> Self {
+        Self(LocalRegisterCopy::new(esr))
+    }
+
+    /// The raw ESR value.
+    pub fn raw(&self) -> u64 {
+        self.0.get()
+    }
+
+    /// The exception class.
+    pub fn exception_class(&self) -> ExceptionClass {
+        ExceptionClass::from(self.0.read(ESR::EC) as u8)
+    }
+
+    /// Returns true if the trapped instruction was 32 bit wide, otherwise 16 bit.
+    pub fn instruction_is_32bit(&self) -> bool {
+        self.0.is_set(ESR::IL)
+    }
+
+    /// The raw instruction specific syndrome.
+    pub fn iss(&self) -> u32 {
+        self.0.read(ESR::ISS) as u32
+    }
+
+    /// The instruction specific syndrome, decoded according to the exception class.
+    pub fn decode(&self) -> Iss {
+        use ExceptionClass::*;
+
+        let iss = self.iss();
+
+        match self.exception_class() {
+            DataAbortLowerEL | DataAbortCurrentEL => Iss::DataAbort(decode_data_abort(iss)),
+            InstructionAbortLowerEL | InstructionAbortCurrentEL => {
+                Iss::InstructionAbort(decode_instruction_abort(iss))
+            }
+            SvcAArch32 | SvcAArch64 => Iss::Svc(decode_immediate(iss)),
+            HvcAArch32 | HvcAArch64 => Iss::Hvc(decode_immediate(iss)),
+            SmcAArch32 | SmcAArch64 => Iss::Smc(decode_immediate(iss)),
+            BrkAArch64 => Iss::Brk(decode_immediate(iss)),
+            TrappedMsrMrs => Iss::SysRegTrap(decode_sys_reg_trap(iss)),
+            BreakpointLowerEL | BreakpointCurrentEL => Iss::Breakpoint,
+            WatchpointLowerEL | WatchpointCurrentEL => Iss::Watchpoint(decode_watchpoint(iss)),
+            SoftwareStepLowerEL | SoftwareStepCurrentEL => {
+                Iss::SoftwareStep(decode_software_step(iss))
+            }
+            _ => Iss::Raw(iss),
+        }
+    }
+}
+
+fn decode_data_abort(iss: u32) -> DataAbort {
+    let iss = LocalRegisterCopy::<u32, ISS_DATA_ABORT::Register>::new(iss);
+
+    let syndrome = if iss.is_set(ISS_DATA_ABORT::ISV) {
+        let access_size = match iss.read_as_enum(ISS_DATA_ABORT::SAS) {
+            Some(ISS_DATA_ABORT::SAS::Value::Byte) => AccessSize::Byte,
+            Some(ISS_DATA_ABORT::SAS::Value::Halfword) => AccessSize::Halfword,
+            Some(ISS_DATA_ABORT::SAS::Value::Word) => AccessSize::Word,
+            _ => AccessSize::Doubleword,
+        };
+
+        Some(InstructionSyndrome {
+            access_size,
+            sign_extend: iss.is_set(ISS_DATA_ABORT::SSE),
+            register: iss.read(ISS_DATA_ABORT::SRT) as u8,
+            sixty_four_bit: iss.is_set(ISS_DATA_ABORT::SF),
+            acquire_release: iss.is_set(ISS_DATA_ABORT::AR),
+        })
+    } else {
+        None
+    };
+
+    DataAbort {
+        syndrome,
+        far_not_valid: iss.is_set(ISS_DATA_ABORT::FnV),
+        external_abort: iss.is_set(ISS_DATA_ABORT::EA),
+        cache_maintenance: iss.is_set(ISS_DATA_ABORT::CM),
+        s1ptw: iss.is_set(ISS_DATA_ABORT::S1PTW),
+        write: iss.is_set(ISS_DATA_ABORT::WnR),
+        status: FaultStatus::from(iss.read(ISS_DATA_ABORT::DFSC) as u8),
+    }
+}
+
+fn decode_instruction_abort(iss: u32) -> InstructionAbort {
+    let iss = LocalRegisterCopy::<u32, ISS_INSTRUCTION_ABORT::Register>::new(iss);
+
+    InstructionAbort {
+        far_not_valid: iss.is_set(ISS_INSTRUCTION_ABORT::FnV),
+        external_abort: iss.is_set(ISS_INSTRUCTION_ABORT::EA),
+        s1ptw: iss.is_set(ISS_INSTRUCTION_ABORT::S1PTW),
+        status: FaultStatus::from(iss.read(ISS_INSTRUCTION_ABORT::IFSC) as u8),
+    }
+}
+
+fn decode_immediate(iss: u32) -> u16 {
+    LocalRegisterCopy::<u32, ISS_IMMEDIATE::Register>::new(iss).read(ISS_IMMEDIATE::IMM16) as u16
+}
+
+fn decode_sys_reg_trap(iss: u32) -> SysRegTrap {
+    let iss = LocalRegisterCopy::<u32, ISS_SYS_REG::Register>::new(iss);
+
+    let direction = if iss.is_set(ISS_SYS_REG::Direction) {
+        SysRegDirection::Read
+    } else {
+        SysRegDirection::Write
+    };
+
+    SysRegTrap {
+        op0: iss.read(ISS_SYS_REG::Op0) as u8,
+        op1: iss.read(ISS_SYS_REG::Op1) as u8,
+        crn: iss.read(ISS_SYS_REG::CRn) as u8,
+        crm: iss.read(ISS_SYS_REG::CRm) as u8,
+        op2: iss.read(ISS_SYS_REG::Op2) as u8,
+        register: iss.read(ISS_SYS_REG::Rt) as u8,
+        direction,
+    }
+}
+
+fn decode_watchpoint(iss: u32) -> Watchpoint {
+    let iss = LocalRegisterCopy::<u32, ISS_DATA_ABORT::Register>::new(iss);
+
+    Watchpoint {
+        cache_maintenance: iss.is_set(ISS_DATA_ABORT::CM),
+        write: iss.is_set(ISS_DATA_ABORT::WnR),
+    }
+}
+
+fn decode_software_step(iss: u32) -> SoftwareStep {
+    let iss = LocalRegisterCopy::<u32, ISS_SOFTWARE_STEP::Register>::new(iss);
+
+    let exclusive = if iss.is_set(ISS_SOFTWARE_STEP::ISV) {
+        Some(iss.is_set(ISS_SOFTWARE_STEP::EX))
+    } else {
+        None
+    };
+
+    SoftwareStep { exclusive }
+}
+
+//------------------------------------------------------------------------------
+// Pretty printing
+//------------------------------------------------------------------------------
+
+impl fmt::Display for ExceptionClass {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use ExceptionClass::*;
+
+        let s = match self {
+            Unknown => "Unknown reason",
+            TrappedWfiWfe => "Trapped WFI or WFE",
+            TrappedMcrMrcCp15 => "Trapped MCR or MRC, coproc 0b1111",
+            TrappedMcrrMrrcCp15 => "Trapped MCRR or MRRC, coproc 0b1111",
+            TrappedMcrMrcCp14 => "Trapped MCR or MRC, coproc 0b1110",
+            TrappedLdcStc => "Trapped LDC or STC",
+            TrappedFpSimd => "Trapped SVE, SIMD or floating-point access",
+            TrappedVmrs => "Trapped VMRS",
+            TrappedPointerAuth => "Trapped pointer authentication instruction",
+            TrappedMrrcCp14 => "Trapped MRRC, coproc 0b1110",
+            BranchTarget => "Branch target exception",
+            IllegalExecutionState => "Illegal execution state",
+            SvcAArch32 => "SVC, AArch32",
+            HvcAArch32 => "HVC, AArch32",
+            SmcAArch32 => "SMC, AArch32",
+            SvcAArch64 => "SVC, AArch64",
+            HvcAArch64 => "HVC, AArch64",
+            SmcAArch64 => "SMC, AArch64",
+            TrappedMsrMrs => "Trapped MSR, MRS or System instruction",
+            TrappedSve => "Trapped SVE access",
+            TrappedEret => "Trapped ERET",
+            PointerAuthFailure => "Pointer authentication failure",
+            ImplementationDefinedEL3 => "Implementation defined, EL3",
+            InstructionAbortLowerEL => "Instruction Abort, lower EL",
+            InstructionAbortCurrentEL => "Instruction Abort, current EL",
+            PcAlignmentFault => "PC alignment fault",
+            DataAbortLowerEL => "Data Abort, lower EL",
+            DataAbortCurrentEL => "Data Abort, current EL",
+            SpAlignmentFault => "SP alignment fault",
+            TrappedFpAArch32 => "Trapped floating-point exception, AArch32",
+            TrappedFpAArch64 => "Trapped floating-point exception, AArch64",
+            SError => "SError interrupt",
+            BreakpointLowerEL => "Breakpoint, lower EL",
+            BreakpointCurrentEL => "Breakpoint, current EL",
+            SoftwareStepLowerEL => "Software Step, lower EL",
+            SoftwareStepCurrentEL => "Software Step, current EL",
+            WatchpointLowerEL => "Watchpoint, lower EL",
+            WatchpointCurrentEL => "Watchpoint, current EL",
+            BkptAArch32 => "BKPT, AArch32",
+            VectorCatchAArch32 => "Vector catch, AArch32",
+            BrkAArch64 => "BRK, AArch64",
+            Reserved(_) => "Reserved",
+        };
+
+        write!(f, "{}", s)
+    }
+}
+
+impl fmt::Display for FaultStatus {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use FaultStatus::*;
+
+        match self {
+            AddressSize(l) => write!(f, "Address size fault, level {}", l),
+            Translation(l) => write!(f, "Translation fault, level {}", l),
+            AccessFlag(l) => write!(f, "Access flag fault, level {}", l),
+            Permission(l) => write!(f, "Permission fault, level {}", l),
+            SyncExternal => write!(f, "Synchronous external abort"),
+            SyncTagCheck => write!(f, "Synchronous tag check fault"),
+            SyncExternalOnWalk(l) => {
+                write!(f, "Synchronous external abort on table walk, level {}", l)
+            }
+            SyncParity => write!(f, "Synchronous parity or ECC error"),
+            SyncParityOnWalk(l) => {
+                write!(
+                    f,
+                    "Synchronous parity or ECC error on table walk, level {}",
+                    l
+                )
+            }
+            Alignment => write!(f, "Alignment fault"),
+            Debug => write!(f, "Debug exception"),
+            TlbConflict => write!(f, "TLB conflict abort"),
+            UnsupportedAtomicUpdate => write!(f, "Unsupported atomic hardware update"),
+            Lockdown => write!(f, "Lockdown"),
+            UnsupportedExclusive => write!(f, "Unsupported exclusive or atomic access"),
+            Other(fsc) => write!(f, "Unknown fault status {:#x}", fsc),
+        }
+    }
+}
+
+/// Human readable ISS.
+#[rustfmt::skip]
+impl fmt::Display for Iss {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Iss::DataAbort(da) => {
+                writeln!(f, "      Access: {}", if da.write { "Write" } else { "Read" })?;
+                if let Some(s) = da.syndrome {
+                    writeln!(f, "      Size: {:?}, Register: x{}", s.access_size, s.register)?;
+                }
+                if da.far_not_valid {
+                    writeln!(f, "      FAR_EL1 is not valid")?;
+                }
+                write!(f, "      Fault: {}", da.status)
+            }
+            Iss::InstructionAbort(ia) => {
+                if ia.far_not_valid {
+                    writeln!(f, "      FAR_EL1 is not valid")?;
+                }
+                write!(f, "      Fault: {}", ia.status)
+            }
+            Iss::Svc(imm) => write!(f, "      SVC #{:#x}", imm),
+            Iss::Hvc(imm) => write!(f, "      HVC #{:#x}", imm),
+            Iss::Smc(imm) => write!(f, "      SMC #{:#x}", imm),
+            Iss::Brk(imm) => write!(f, "      BRK #{:#x}", imm),
+            Iss::SysRegTrap(t) => write!(f,
+                "      {} S{}_{}_C{}_C{}_{}, x{}",
+                match t.direction {
+                    SysRegDirection::Read => "MRS",
+                    SysRegDirection::Write => "MSR",
+                },
+                t.op0, t.op1, t.crn, t.crm, t.op2, t.register
+            ),
+            Iss::Breakpoint => write!(f, "      Hardware breakpoint"),
+            Iss::Watchpoint(w) => {
+                write!(f, "      Access: {}", if w.write { "Write" } else { "Read" })
+            }
+            Iss::SoftwareStep(s) => match s.exclusive {
+                Some(true) => write!(f, "      Stepped a load-exclusive"),
+                _ => write!(f, "      Stepped instruction"),
+            },
+            Iss::Raw(iss) => write!(f, "      Not decoded: {:#x}", iss),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn data_abort() {
+        // STR w3, [x0] to an unmapped page, taken at the current EL: EC 0x25, IL, ISV, SAS word,
+        // SRT 3, WnR, DFSC translation fault at level 3.
+        let syndrome = Syndrome::from_raw(0x9783_0047);
+
+        assert_eq!(
+            syndrome.exception_class(),
+            ExceptionClass::DataAbortCurrentEL
+        );
+        assert!(syndrome.exception_class().is_abort());
+        assert!(syndrome.instruction_is_32bit());
+        assert_eq!(
+            syndrome.decode(),
+            Iss::DataAbort(DataAbort {
+                syndrome: Some(InstructionSyndrome {
+                    access_size: AccessSize::Word,
+                    sign_extend: false,
+                    register: 3,
+                    sixty_four_bit: false,
+                    acquire_release: false,
+                }),
+                far_not_valid: false,
+                external_abort: false,
+                cache_maintenance: false,
+                s1ptw: false,
+                write: true,
+                status: FaultStatus::Translation(3),
+            })
+        );
+    }
+
+    #[test]
+    fn data_abort_without_instruction_syndrome() {
+        // Read from a lower EL without ISV, FAR not valid, permission fault at level 2.
+        let syndrome = Syndrome::from_raw(0x9200_040E);
+
+        assert_eq!(syndrome.exception_class(), ExceptionClass::DataAbortLowerEL);
+        assert_eq!(
+            syndrome.decode(),
+            Iss::DataAbort(DataAbort {
+                syndrome: None,
+                far_not_valid: true,
+                external_abort: false,
+                cache_maintenance: false,
+                s1ptw: false,
+                write: false,
+                status: FaultStatus::Permission(2),
+            })
+        );
+    }
+
+    #[test]
+    fn svc() {
+        let syndrome = Syndrome::from_raw(0x5600_0042);
+
+        assert_eq!(syndrome.exception_class(), ExceptionClass::SvcAArch64);
+        assert!(!syndrome.exception_class().is_abort());
+        assert_eq!(syndrome.decode(), Iss::Svc(0x42));
+    }
+
+    #[test]
+    fn hvc() {
+        let syndrome = Syndrome::from_raw(0x5A00_1234);
+
+        assert_eq!(syndrome.exception_class(), ExceptionClass::HvcAArch64);
+        assert_eq!(syndrome.decode(), Iss::Hvc(0x1234));
+    }
+
+    #[test]
+    fn trapped_mrs() {
+        // MRS x5, CNTVCT_EL0, which is S3_3_C14_C0_2.
+        let syndrome = Syndrome::from_raw(0x6234_F8A1);
+        let expected = SysRegTrap {
+            op0: 3,
+            op1: 3,
+            crn: 14,
+            crm: 0,
+            op2: 2,
+            register: 5,
+            direction: SysRegDirection::Read,
+        };
+
+        assert_eq!(syndrome.exception_class(), ExceptionClass::TrappedMsrMrs);
+        assert_eq!(syndrome.decode(), Iss::SysRegTrap(expected));
+        assert_eq!(
+            format!("{}", syndrome.decode()),
+            "      MRS S3_3_C14_C0_2, x5"
+        );
+
+        // The same access as MSR.
+        assert_eq!(
+            Syndrome::from_raw(0x6234_F8A0).decode(),
+            Iss::SysRegTrap(SysRegTrap {
+                direction: SysRegDirection::Write,
+                ..expected
+            })
+        );
+    }
+
+    #[test]
+    fn unknown_exception_class() {
+        let syndrome = Syndrome::from_raw(0x0200_0000);
+
+        assert_eq!(syndrome.exception_class(), ExceptionClass::Unknown);
+        assert_eq!(syndrome.decode(), Iss::Raw(0));
+
+        // EC 0x3F is reserved, the ISS is passed through undecoded.
+        let syndrome = Syndrome::from_raw(0xFE00_1234);
+
+        assert_eq!(syndrome.exception_class(), ExceptionClass::Reserved(0x3F));
+        assert_eq!(syndrome.raw(), 0xFE00_1234);
+        assert_eq!(syndrome.iss(), 0x1234);
+        assert_eq!(syndrome.decode(), Iss::Raw(0x1234));
+    }
+}
@@ -0,0 +1,95 @@
+//! Global heap allocator over the `.heap` section from the linker script.
+//!
+//! The heap size is configured at build time with the `CORTEX_A_RT_HEAP_SIZE` environment variable,
+//! as a non-zero multiple of the page size. Allocations are serialized with a spin lock, which also
+//! masks IRQs and FIQs on the executing core, so the allocator can be used from all cores and from
+//! interrupt context.
+
+use core::{
+    alloc::{GlobalAlloc, Layout},
+    cell::UnsafeCell,
+    ptr::{self, NonNull},
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use crate::{exception::masking, memory};
+
+/// Heap protected by an interrupt-safe spin lock.
+pub struct LockedHeap {
+    locked: AtomicBool,
+    heap: UnsafeCell<linked_list_allocator::Heap>,
+}
+
+unsafe impl Sync for LockedHeap {}
+
+#[global_allocator]
+static ALLOCATOR: LockedHeap = LockedHeap::empty();
+
+impl LockedHeap {
+    /// Create an empty heap, which fails all allocations until initialized.
+    pub const fn empty() -> Self {
+        Self {
+            locked: AtomicBool::new(false),
+            heap: UnsafeCell::new(linked_list_allocator::Heap::empty()),
+        }
+    }
+
+    /// Runs the closure with exclusive access to the heap.
+    fn with_heap<R>(&self, f: impl FnOnce(&mut linked_list_allocator::Heap) -> R) -> R {
+        unsafe {
+            // Mask interrupts first, so that an interrupt handler on the same core can not deadlock
+            // by trying to take the lock held by the code it interrupted.
+            let state = masking::local_mask_save();
+            masking::local_irq_mask();
+            masking::local_fiq_mask();
+
+            while self
+                .locked
+                .compare_exchange_weak(false, true, Ordering::Acquire, Ordering::Relaxed)
+                .is_err()
+            {
+                core::hint::spin_loop();
+            }
+
+            let res = f(&mut *self.heap.get());
+
+            self.locked.store(false, Ordering::Release);
+            masking::local_mask_restore(state);
+
+            res
+        }
+    }
+}
+
+unsafe impl GlobalAlloc for LockedHeap {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        self.with_heap(|heap| {
+            heap.allocate_first_fit(layout)
+                .map_or(ptr::null_mut(), |allocation| allocation.as_ptr())
+        })
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        self.with_heap(|heap| heap.deallocate(NonNull::new_unchecked(ptr), layout))
+    }
+}
+
+#[alloc_error_handler]
+fn alloc_error(layout: Layout) -> ! {
+    panic!("Heap allocation of {} bytes failed", layout.size());
+}
+
+/// Initialize the global allocator over the `.heap` section.
+///
+/// # Safety
+///
+/// - Must only be called once on startup, after the MMU is enabled, because the spin lock relies on
+///   exclusive accesses to cacheable memory.
+pub unsafe fn init() {
+    // build.rs rejects a zero heap size, so the range is never empty.
+    let heap = memory::heap_range();
+
+    ALLOCATOR.with_heap(|allocator| {
+        allocator.init(heap.start as usize, heap.end as usize - heap.start as usize)
+    });
+}
@@ -0,0 +1,164 @@
+//! Hypervisor support for running a guest payload in EL1.
+//!
+//! With the `hypervisor` feature, the runtime stays in EL2 instead of dropping to EL1. `main()`
+//! executes in EL2 with the EL2 exception vectors and MMU, and IRQs, FIQs and SErrors are routed to
+//! EL2.
+//!
+//! Memory accesses of the guest go through stage 2 translation, which is populated from a
+//! `VirtualMemoryLayout` mapping guest intermediate physical addresses (IPA) to physical addresses.
+//! Ranges which are not mapped, such as MMIO the guest must not touch, cause stage 2 aborts that are
+//! taken to `lower_aarch64_synchronous` in EL2 and passed to the fault handler of the `syscall`
+//! module, where `guest_fault_ipa()` reports the IPA.
+//!
+//! The guest calls into the hypervisor with `hvc #N`, which is dispatched like a syscall, see the
+//! `syscall` module.
+
+use cortex_a::{barrier, regs::*};
+
+use crate::{
+    elx,
+    mmu::{
+        layout::VirtualMemoryLayout,
+        mmu::{phys_addr_range, TranslationTable},
+    },
+};
+
+/// The stage 2 translation table of the guest.
+///
+/// # Safety
+///
+/// - Supposed to land in `.bss`. Therefore, ensure that all initial member values boil down to "0".
+static mut STAGE2_TRANSLATION_TABLE: TranslationTable = TranslationTable::new();
+
+// HCR_EL2 bits.
+const HCR_EL2_VM: u64 = 1 << 0;
+const HCR_EL2_FMO: u64 = 1 << 3;
+const HCR_EL2_IMO: u64 = 1 << 4;
+const HCR_EL2_AMO: u64 = 1 << 5;
+const HCR_EL2_TSC: u64 = 1 << 19;
+const HCR_EL2_RW: u64 = 1 << 31;
+
+// VTCR_EL2 fields.
+const VTCR_EL2_RES1: u64 = 1 << 31;
+const VTCR_EL2_PS_SHIFT: u64 = 16;
+#[cfg(not(feature = "granule-4k"))]
+const VTCR_EL2_TG0: u64 = 0b01 << 14;
+#[cfg(feature = "granule-4k")]
+const VTCR_EL2_TG0: u64 = 0b00 << 14;
+const VTCR_EL2_SH0_INNER: u64 = 0b11 << 12;
+const VTCR_EL2_ORGN0_WRITEBACK: u64 = 0b01 << 10;
+const VTCR_EL2_IRGN0_WRITEBACK: u64 = 0b01 << 8;
+/// Start the walk at the level of the `FixedSizeTranslationTable` root table, level 2 with the
+/// 64 KiB granule and level 0 with the 4 KiB granule.
+#[cfg(not(feature = "granule-4k"))]
+const VTCR_EL2_SL0: u64 = 0b01 << 6;
+#[cfg(feature = "granule-4k")]
+const VTCR_EL2_SL0: u64 = 0b10 << 6;
+
+/// SCTLR_EL1 with only the RES1 bits set. MMU and caches of the guest are off.
+const SCTLR_EL1_RES1: u64 = (1 << 29) | (1 << 28) | (1 << 23) | (1 << 22) | (1 << 20) | (1 << 11);
+
+/// Configures the EL2 controls of the executing core. The guest executes in AArch64, SMC
+/// instructions are trapped and physical interrupts are taken to EL2.
+///
+/// # Safety
+///
+/// - Must be executed in EL2.
+pub(crate) unsafe fn init() {
+    HCR_EL2.set(HCR_EL2_RW | HCR_EL2_IMO | HCR_EL2_FMO | HCR_EL2_AMO | HCR_EL2_TSC);
+
+    barrier::isb(barrier::SY);
+}
+
+/// Populates the stage 2 translation table from the layout and enables stage 2 translation for the
+/// guest.
+///
+/// # Safety
+///
+/// - Changes the memory view of the guest. Must be called before `enter_guest()`.
+pub unsafe fn enable_stage2(layout: &impl VirtualMemoryLayout) -> Result<(), &'static str> {
+    if HCR_EL2.get() & HCR_EL2_VM != 0 {
+        return Err("Stage 2 translation is already enabled");
+    }
+
+    STAGE2_TRANSLATION_TABLE.populate_stage2_tt_entries(layout)?;
+
+    let t0sz = (64 - STAGE2_TRANSLATION_TABLE.addr_space_size().trailing_zeros()) as u64;
+    let vtcr = VTCR_EL2_RES1
+        | (phys_addr_range() << VTCR_EL2_PS_SHIFT)
+        | VTCR_EL2_TG0
+        | VTCR_EL2_SH0_INNER
+        | VTCR_EL2_ORGN0_WRITEBACK
+        | VTCR_EL2_IRGN0_WRITEBACK
+        | VTCR_EL2_SL0
+        | t0sz;
+
+    // Make the table entries visible to the table walker.
+    barrier::dsb(barrier::SY);
+
+    // VMID 0 is used for the single guest.
+    asm!(
+        "msr VTCR_EL2, {vtcr}",
+        "msr VTTBR_EL2, {vttbr}",
+        vtcr = in(reg) vtcr,
+        vttbr = in(reg) STAGE2_TRANSLATION_TABLE.phys_base_address(),
+        options(nomem, nostack)
+    );
+    barrier::isb(barrier::SY);
+
+    HCR_EL2.set(HCR_EL2.get() | HCR_EL2_VM);
+
+    // Discard stale translations of the guest.
+    asm!("tlbi vmalls12e1is", options(nostack));
+    barrier::dsb(barrier::SY);
+    barrier::isb(barrier::SY);
+
+    Ok(())
+}
+
+/// Enters the guest in EL1h at the intermediate physical address `entry`, with `arg` passed in x0
+/// and all interrupts masked. The guest starts with its MMU and caches off and must set up its own
+/// stack.
+///
+/// # Safety
+///
+/// - `enable_stage2()` must have been called, so that the guest can only access what is mapped for
+///   it.
+/// - `entry` must point to guest code.
+pub unsafe fn enter_guest(entry: usize, arg: u64) -> ! {
+    // Enable timer counter registers for EL1.
+    CNTHCTL_EL2.write(CNTHCTL_EL2::EL1PCEN::SET + CNTHCTL_EL2::EL1PCTEN::SET);
+
+    // No offset for reading the counters.
+    CNTVOFF_EL2.set(0);
+
+    SCTLR_EL1.set(SCTLR_EL1_RES1);
+
+    SPSR_EL2.write(
+        SPSR_EL2::D::Masked
+            + SPSR_EL2::A::Masked
+            + SPSR_EL2::I::Masked
+            + SPSR_EL2::F::Masked
+            + SPSR_EL2::M::EL1h,
+    );
+    ELR_EL2.set(entry as u64);
+
+    asm!("eret", in("x0") arg, options(noreturn, nostack))
+}
+
+/// Returns the intermediate physical address, which caused the stage 2 abort currently being
+/// handled.
+pub fn guest_fault_ipa() -> usize {
+    let hpfar: u64;
+
+    unsafe {
+        asm!("mrs {}, HPFAR_EL2", out(reg) hpfar, options(nomem, nostack, preserves_flags));
+    }
+
+    // HPFAR_EL2.FIPA [43:4] holds bits [51:12] of the IPA, the page offset is taken from FAR_EL2.
+    const FIPA_MASK: u64 = 0x0000_0FFF_FFFF_FFF0;
+    let page = (hpfar & FIPA_MASK) << 8;
+    let offset = elx::far() & 0xFFF;
+
+    (page | offset) as usize
+}
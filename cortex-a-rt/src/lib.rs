@@ -2,36 +2,134 @@
 #![feature(const_panic)]
 #![feature(linkage)]
 #![feature(asm)]
-#![no_std]
+#![cfg_attr(feature = "alloc", feature(alloc_error_handler))]
+// Tests of the architecture independent modules run on the host, see the README.
+#![cfg_attr(not(test), no_std)]
 
+#[cfg(target_arch = "aarch64")]
 use cortex_a::regs::RegisterReadOnly;
+#[cfg(target_arch = "aarch64")]
 use register::Field;
 
+#[cfg(all(feature = "higher-half", feature = "hypervisor"))]
+compile_error!("The `higher-half` feature needs TTBR1_EL1, which the EL2 translation regime lacks");
+
+#[cfg(target_arch = "aarch64")]
+pub mod cache;
+pub mod cmdline;
+#[cfg(target_arch = "aarch64")]
+pub mod dtb;
+#[cfg(all(target_arch = "aarch64", not(feature = "hypervisor")))]
+pub mod el0;
+#[cfg(target_arch = "aarch64")]
+mod elx;
 pub mod exception;
+#[cfg(all(target_arch = "aarch64", feature = "alloc"))]
+pub mod heap;
+#[cfg(all(target_arch = "aarch64", feature = "hypervisor"))]
+pub mod hyp;
+#[cfg(target_arch = "aarch64")]
 pub mod memory;
 pub mod mmu;
+#[cfg(target_arch = "aarch64")]
+pub mod probe;
+#[cfg(all(target_arch = "aarch64", feature = "entry"))]
+pub mod smp;
+#[cfg(target_arch = "aarch64")]
+pub mod syscall;
 
-#[cfg(feature = "entry")]
+#[cfg(all(target_arch = "aarch64", feature = "entry"))]
 pub mod entry {
+    use core::sync::atomic::{AtomicU8, Ordering};
     use cortex_a::{asm, regs::*};
 
-    use crate::{exception, memory, mmu};
+    use crate::{dtb, exception, memory, mmu, smp};
+
+    #[cfg(feature = "higher-half")]
+    macro_rules! higher_half {
+        () => {
+            ".equ _HIGHER_HALF, 1\n"
+        };
+    }
+    #[cfg(not(feature = "higher-half"))]
+    macro_rules! higher_half {
+        () => {
+            ".equ _HIGHER_HALF, 0\n"
+        };
+    }
+
+    #[cfg(feature = "granule-4k")]
+    macro_rules! granule_4k {
+        () => {
+            ".equ _GRANULE_4K, 1\n"
+        };
+    }
+    #[cfg(not(feature = "granule-4k"))]
+    macro_rules! granule_4k {
+        () => {
+            ".equ _GRANULE_4K, 0\n"
+        };
+    }
 
     // Initial boot handled by assembly
-    global_asm!(include_str!("boot.s"));
+    global_asm!(concat!(
+        higher_half!(),
+        granule_4k!(),
+        include_str!("boot.s")
+    ));
+
+    // With the `higher-half` feature, the EL1 entry functions first switch on the MMU with the boot
+    // translation table, see boot.s.
+    #[cfg(feature = "higher-half")]
+    extern "C" {
+        fn _start_main_high() -> !;
+        fn _start_main_secondary_high() -> !;
+    }
 
     #[no_mangle]
     #[link_section = ".text._start_arguments"]
     pub static BOOT_CORE_ID: u64 = 0;
 
+    /// Exception level in which the boot core entered `_start`.
+    ///
+    /// Placed in `.data`, because it is written before `.bss` is zeroed.
+    #[link_section = ".data.boot_el"]
+    static BOOT_EL: AtomicU8 = AtomicU8::new(0);
+
+    /// Exception levels the runtime can be started in.
+    #[derive(Copy, Clone, Debug, PartialEq, Eq)]
+    pub enum ExceptionLevel {
+        EL1,
+        EL2,
+        EL3,
+    }
+
+    /// Returns the exception level in which the boot core was started.
+    ///
+    /// Independent of the boot exception level, `main()` always executes in EL1, or in EL2 with the
+    /// `hypervisor` feature. Starting in EL3 transitions through EL2 to EL1, starting in EL1 leaves
+    /// the EL2 configuration to whoever started the runtime.
+    pub fn boot_el() -> ExceptionLevel {
+        match BOOT_EL.load(Ordering::Relaxed) {
+            1 => ExceptionLevel::EL1,
+            2 => ExceptionLevel::EL2,
+            3 => ExceptionLevel::EL3,
+            el => unreachable!("Invalid boot exception level {}", el),
+        }
+    }
+
     /// Prepares the transition from EL2 to EL1.
     ///
     /// # Safety
     ///
     /// - The `bss` section is not initialized yet. The code must not use or reference it in any way.
     /// - The HW state of EL1 must be prepared in a sound way.
+    #[cfg(not(feature = "hypervisor"))]
     #[inline(always)]
-    unsafe fn prepare_el2_to_el1_transition(phys_boot_core_stack_end_exclusive_addr: u64) {
+    unsafe fn prepare_el2_to_el1_transition(
+        phys_stack_end_exclusive_addr: u64,
+        el1_entry: unsafe extern "C" fn() -> !,
+    ) {
         // Enable timer counter registers for EL1.
         CNTHCTL_EL2.write(CNTHCTL_EL2::EL1PCEN::SET + CNTHCTL_EL2::EL1PCTEN::SET);
 
@@ -53,22 +151,87 @@ pub mod entry {
                 + SPSR_EL2::M::EL1h,
         );
 
-        // Second, let the link register point to _start_main().
-        ELR_EL2.set(_start_main as *const () as u64);
+        // Second, let the link register point to the EL1 entry function.
+        ELR_EL2.set(el1_entry as *const () as u64);
 
         // Set up SP_EL1 (stack pointer), which will be used by EL1 once we "return" to it. Since there
         // are no plans to ever return to EL2, just re-use the same stack.
-        SP_EL1.set(phys_boot_core_stack_end_exclusive_addr);
+        SP_EL1.set(phys_stack_end_exclusive_addr);
     }
 
-    #[no_mangle]
-    pub unsafe extern "C" fn _start_rust(phys_boot_core_stack_end_exclusive_addr: u64) -> ! {
-        prepare_el2_to_el1_transition(phys_boot_core_stack_end_exclusive_addr);
+    /// Continues execution at `el1_entry` in EL1. Cores in EL3 were already moved to EL2 by
+    /// `boot.s`.
+    ///
+    /// # Safety
+    ///
+    /// - See `prepare_el2_to_el1_transition()`.
+    #[cfg(not(feature = "hypervisor"))]
+    #[inline(always)]
+    unsafe fn enter_runtime_el(
+        phys_stack_end_exclusive_addr: u64,
+        el1_entry: unsafe extern "C" fn() -> !,
+    ) -> ! {
+        if CurrentEL.matches_all(CurrentEL::EL::EL1) {
+            // Already in EL1, boot.s has set up the stack.
+            el1_entry()
+        }
+
+        prepare_el2_to_el1_transition(phys_stack_end_exclusive_addr, el1_entry);
 
-        // Use `eret` to "return" to EL1. This results in execution of _start_main() in EL1.
+        // Use `eret` to "return" to EL1. This results in execution of el1_entry() in EL1.
         asm::eret()
     }
 
+    /// Continues execution at `el2_entry` in EL2. Cores in EL3 were already moved to EL2 by
+    /// `boot.s`. Cores started in EL1 are parked, because the hypervisor needs EL2.
+    ///
+    /// # Safety
+    ///
+    /// - The `bss` section is not initialized yet. The code must not use or reference it in any way.
+    #[cfg(feature = "hypervisor")]
+    #[inline(always)]
+    unsafe fn enter_runtime_el(
+        _phys_stack_end_exclusive_addr: u64,
+        el2_entry: unsafe extern "C" fn() -> !,
+    ) -> ! {
+        if !CurrentEL.matches_all(CurrentEL::EL::EL2) {
+            loop {
+                asm::wfe();
+            }
+        }
+
+        crate::hyp::init();
+
+        // boot.s has set up SP_EL2.
+        el2_entry()
+    }
+
+    #[no_mangle]
+    pub unsafe extern "C" fn _start_rust(
+        phys_boot_core_stack_end_exclusive_addr: u64,
+        boot_el: u64,
+        dtb_addr: u64,
+    ) -> ! {
+        BOOT_EL.store(boot_el as u8, Ordering::Relaxed);
+        dtb::set_addr(dtb_addr as usize);
+
+        #[cfg(not(feature = "higher-half"))]
+        enter_runtime_el(phys_boot_core_stack_end_exclusive_addr, _start_main);
+        #[cfg(feature = "higher-half")]
+        enter_runtime_el(phys_boot_core_stack_end_exclusive_addr, _start_main_high)
+    }
+
+    #[no_mangle]
+    pub unsafe extern "C" fn _start_rust_secondary(phys_core_stack_end_exclusive_addr: u64) -> ! {
+        #[cfg(not(feature = "higher-half"))]
+        enter_runtime_el(phys_core_stack_end_exclusive_addr, _start_main_secondary);
+        #[cfg(feature = "higher-half")]
+        enter_runtime_el(
+            phys_core_stack_end_exclusive_addr,
+            _start_main_secondary_high,
+        )
+    }
+
     #[no_mangle]
     pub unsafe extern "C" fn _start_main() -> ! {
         extern "Rust" {
@@ -81,9 +244,23 @@ pub mod entry {
             .enable_mmu_and_caching(&mmu::layout::default::default_layout())
             .expect("Failed to initialize MMU");
 
+        #[cfg(feature = "alloc")]
+        crate::heap::init();
+
         main();
     }
 
+    #[no_mangle]
+    pub unsafe extern "C" fn _start_main_secondary() -> ! {
+        // The boot core has already zeroed .bss and populated the translation tables.
+        exception::handling_init();
+        mmu::mmu()
+            .enable_mmu_and_caching_secondary()
+            .expect("Failed to initialize MMU");
+
+        smp::secondary_main();
+    }
+
     // Entry point is _start function in boot.s
     #[doc(hidden)]
     #[no_mangle]
@@ -94,7 +271,11 @@ pub mod entry {
     }
 }
 
+/// Number of cores on the RPi4
+pub const NUM_CORES: usize = 4;
+
 /// Rertuns number of the core which is currently executing this function
+#[cfg(target_arch = "aarch64")]
 pub fn core_id() -> u8 {
     cortex_a::regs::MPIDR_EL1.read(Field::<u64, ()>::new(0b11, 0x0)) as u8
 }
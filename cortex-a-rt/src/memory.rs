@@ -1,4 +1,11 @@
-use core::{cell::UnsafeCell, ops::RangeInclusive};
+use core::{
+    cell::UnsafeCell,
+    ops::{Range, RangeInclusive},
+};
+
+use crate::NUM_CORES;
+
+pub use crate::mmu::translation_table::KERNEL_VIRT_OFFSET;
 
 /// Zero out an inclusive memory range.
 ///
@@ -23,6 +30,12 @@ where
 extern "Rust" {
     static __bss_start: UnsafeCell<u64>;
     static __bss_end_inclusive: UnsafeCell<u64>;
+    static __heap_start: UnsafeCell<u8>;
+    static __heap_end_exclusive: UnsafeCell<u8>;
+    static __stacks_start: UnsafeCell<u8>;
+    static __core_stack_size: UnsafeCell<()>;
+    static __core_stack_stride: UnsafeCell<()>;
+    static __stack_guard_size: UnsafeCell<()>;
 }
 
 /// Return the inclusive range spanning the .bss section.
@@ -41,6 +54,81 @@ pub fn bss_range_inclusive() -> RangeInclusive<*mut u64> {
     range
 }
 
+/// Return the range spanning the .heap section.
+///
+/// The size is configured at build time with the `CORTEX_A_RT_HEAP_SIZE` environment variable.
+pub fn heap_range() -> Range<*mut u8> {
+    unsafe { __heap_start.get()..__heap_end_exclusive.get() }
+}
+
+/// Return the stack size of each core.
+///
+/// The size is configured at build time with the `CORTEX_A_RT_STACK_SIZE` environment variable.
+pub fn core_stack_size() -> usize {
+    // The linker symbol is absolute, so its address is the value.
+    unsafe { __core_stack_size.get() as usize }
+}
+
+/// Return the size of the unmapped guard region below each core's stack, at least 64 KiB.
+pub fn stack_guard_size() -> usize {
+    // The linker symbol is absolute, so its address is the value.
+    unsafe { __stack_guard_size.get() as usize }
+}
+
+/// Return the start address of the region reserved for the given core, which consists of a guard
+/// page followed by the stack.
+fn core_stack_region_start(core: usize) -> *mut u8 {
+    assert!(core < NUM_CORES);
+
+    // The linker symbol is absolute, so its address is the value.
+    unsafe {
+        __stacks_start
+            .get()
+            .add(core * __core_stack_stride.get() as usize)
+    }
+}
+
+/// Return the range spanning the stack of the given core. The stack grows down from the end of
+/// the range.
+pub fn core_stack_range(core: usize) -> Range<*mut u8> {
+    unsafe {
+        let start = core_stack_region_start(core).add(stack_guard_size());
+        start..start.add(core_stack_size())
+    }
+}
+
+/// Return the range spanning the guard page below the stack of the given core.
+pub fn core_stack_guard_range(core: usize) -> Range<*mut u8> {
+    unsafe {
+        let start = core_stack_region_start(core);
+        start..start.add(stack_guard_size())
+    }
+}
+
+/// Return the core whose stack guard page contains the given address.
+pub fn stack_guard_owner(addr: usize) -> Option<usize> {
+    (0..NUM_CORES).find(|&core| {
+        let guard = core_stack_guard_range(core);
+        (guard.start as usize..guard.end as usize).contains(&addr)
+    })
+}
+
+/// Returns the virtual address, at which the runtime's translation tables map the physical address.
+///
+/// With the `higher-half` feature, the runtime only accesses memory and devices through their high
+/// alias, because the lower half is left to per-task tables. Otherwise, memory is identity mapped.
+#[inline(always)]
+pub const fn phys_to_virt(phys_addr: usize) -> usize {
+    phys_addr + KERNEL_VIRT_OFFSET
+}
+
+/// Returns the physical address of a virtual address of the runtime, such as the address of a
+/// static. The inverse of `phys_to_virt()`.
+#[inline(always)]
+pub const fn virt_to_phys(virt_addr: usize) -> usize {
+    virt_addr - KERNEL_VIRT_OFFSET
+}
+
 /// Zero out the .bss section.
 ///
 /// # Safety
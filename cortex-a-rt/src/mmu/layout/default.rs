@@ -1,18 +1,60 @@
-use core::{cell::UnsafeCell, ops::RangeInclusive};
+use core::{
+    cell::UnsafeCell,
+    ops::RangeInclusive,
+    sync::atomic::{AtomicUsize, Ordering},
+};
 
-use crate::mmu::{
-    AccessPermissions, AddressSpace, AttributeFields, MemAttributes, Translation,
-    TranslationDescriptor,
+use crate::{
+    dtb, memory,
+    mmu::{AccessPermissions, AttributeFields, MemAttributes, Translation, TranslationDescriptor},
 };
 
 use super::{simple::SimpleMemoryLayout, VirtualMemoryLayout};
 
-const MEMORY_END_INCLUSIVE: usize = 0xFFFFFFFF;
-pub type DefaultAddrSpace = AddressSpace<{ MEMORY_END_INCLUSIVE + 1 }>;
+/// End of the 32 bit address space, which is always mapped and contains the low peripherals.
+const LOW_ADDR_SPACE_END_INCLUSIVE: usize = 0xFFFF_FFFF;
+
+/// MMIO range of the RPi4, used if the device tree is not available.
+const DEFAULT_MMIO_START: usize = 0xFE00_0000;
+const DEFAULT_MMIO_END_INCLUSIVE: usize = 0xFF84_FFFF;
+
+/// PCIe outbound window of the RPi4, used if the device tree is not available.
+const DEFAULT_HIGH_MMIO_START: usize = 0x6_0000_0000;
+const DEFAULT_HIGH_MMIO_END_INCLUSIVE: usize = 0x6_3FFF_FFFF;
+
+/// End of RAM, used if the device tree is not available.
+const DEFAULT_RAM_END_EXCLUSIVE: usize = LOW_ADDR_SPACE_END_INCLUSIVE + 1;
+
+/// Whether EL0 may access the `.el0_text` and `.el0_data` sections, which all tasks share. Not with
+/// the `higher-half` feature, where each task gets its own translation table, see the `el0` module.
+const EL0_SECTIONS_ACCESSIBLE: bool = !cfg!(feature = "higher-half");
+
+/// MMIO range used by the layout, derived from the device tree by `default_layout()`.
+static MMIO_START: AtomicUsize = AtomicUsize::new(DEFAULT_MMIO_START);
+static MMIO_END_INCLUSIVE: AtomicUsize = AtomicUsize::new(DEFAULT_MMIO_END_INCLUSIVE);
 
+/// MMIO range above the 32 bit address space, derived from the device tree by `default_layout()`.
+/// Empty if the board has none.
+static HIGH_MMIO_START: AtomicUsize = AtomicUsize::new(DEFAULT_HIGH_MMIO_START);
+static HIGH_MMIO_END_INCLUSIVE: AtomicUsize = AtomicUsize::new(DEFAULT_HIGH_MMIO_END_INCLUSIVE);
+
+/// End of RAM, derived from the device tree by `default_layout()`.
+static RAM_END_EXCLUSIVE: AtomicUsize = AtomicUsize::new(DEFAULT_RAM_END_EXCLUSIVE);
+
+/// Returns the default layout, which spans the 32 bit address space, all of RAM and the MMIO range
+/// above the 32 bit address space. The hole between the end of RAM and the high MMIO range is left
+/// unmapped.
+///
+/// The layout describes physical addresses, which are identity mapped. With the `higher-half`
+/// feature, they appear at `memory::phys_to_virt()`, so the kernel's ranges are converted back
+/// from their link addresses.
 pub fn default_layout() -> impl VirtualMemoryLayout {
+    init_mmio_range();
+    init_high_mmio_range();
+    init_ram_end();
+
     SimpleMemoryLayout::new(
-        MEMORY_END_INCLUSIVE,
+        max_virt_addr_inclusive(),
         [
             TranslationDescriptor {
                 name: "Kernel code and RO data",
@@ -22,6 +64,32 @@ pub fn default_layout() -> impl VirtualMemoryLayout {
                     mem_attributes: MemAttributes::CacheableDRAM,
                     acc_perms: AccessPermissions::ReadOnly,
                     execute_never: false,
+                    el0_access: false,
+                    el0_execute_never: true,
+                },
+            },
+            TranslationDescriptor {
+                name: "EL0 code",
+                virtual_range: el0_rx_range_inclusive,
+                physical_range_translation: Translation::Identity,
+                attribute_fields: AttributeFields {
+                    mem_attributes: MemAttributes::CacheableDRAM,
+                    acc_perms: AccessPermissions::ReadOnly,
+                    execute_never: true,
+                    el0_access: EL0_SECTIONS_ACCESSIBLE,
+                    el0_execute_never: false,
+                },
+            },
+            TranslationDescriptor {
+                name: "EL0 data and stacks",
+                virtual_range: el0_rw_range_inclusive,
+                physical_range_translation: Translation::Identity,
+                attribute_fields: AttributeFields {
+                    mem_attributes: MemAttributes::CacheableDRAM,
+                    acc_perms: AccessPermissions::ReadWrite,
+                    execute_never: true,
+                    el0_access: EL0_SECTIONS_ACCESSIBLE,
+                    el0_execute_never: true,
                 },
             },
             TranslationDescriptor {
@@ -32,37 +100,234 @@ pub fn default_layout() -> impl VirtualMemoryLayout {
                     mem_attributes: MemAttributes::Device,
                     acc_perms: AccessPermissions::ReadWrite,
                     execute_never: true,
+                    el0_access: false,
+                    el0_execute_never: true,
                 },
             },
+            TranslationDescriptor {
+                name: "Device MMIO (high)",
+                virtual_range: high_mmio_range_inclusive,
+                physical_range_translation: Translation::Identity,
+                attribute_fields: AttributeFields {
+                    mem_attributes: MemAttributes::Device,
+                    acc_perms: AccessPermissions::ReadWrite,
+                    execute_never: true,
+                    el0_access: false,
+                    el0_execute_never: true,
+                },
+            },
+            TranslationDescriptor {
+                name: "Unpopulated",
+                virtual_range: unpopulated_range_inclusive,
+                physical_range_translation: Translation::Unmapped,
+                attribute_fields: AttributeFields::default(),
+            },
+            stack_guard_descriptor("Core 0 stack guard", stack_guard_range_inclusive::<0>),
+            stack_guard_descriptor("Core 1 stack guard", stack_guard_range_inclusive::<1>),
+            stack_guard_descriptor("Core 2 stack guard", stack_guard_range_inclusive::<2>),
+            stack_guard_descriptor("Core 3 stack guard", stack_guard_range_inclusive::<3>),
         ],
     )
 }
 
+/// Unmapped page below the stack of a core, so that stack overflows fault instead of corrupting
+/// memory.
+fn stack_guard_descriptor(
+    name: &'static str,
+    virtual_range: fn() -> RangeInclusive<usize>,
+) -> TranslationDescriptor {
+    TranslationDescriptor {
+        name,
+        virtual_range,
+        physical_range_translation: Translation::Unmapped,
+        attribute_fields: AttributeFields::default(),
+    }
+}
+
 fn rx_range_inclusive() -> RangeInclusive<usize> {
     // Notice the subtraction to turn the exclusive end into an inclusive end.
     #[allow(clippy::range_minus_one)]
     RangeInclusive::new(rx_start(), rx_end_exclusive() - 1)
 }
 
+fn el0_rx_range_inclusive() -> RangeInclusive<usize> {
+    // Notice the subtraction to turn the exclusive end into an inclusive end. The range is empty
+    // if there is no EL0 code.
+    #[allow(clippy::range_minus_one)]
+    RangeInclusive::new(el0_rx_start(), el0_rx_end_exclusive().wrapping_sub(1))
+}
+
+fn el0_rw_range_inclusive() -> RangeInclusive<usize> {
+    // Notice the subtraction to turn the exclusive end into an inclusive end. The range is empty
+    // if there is no EL0 data.
+    #[allow(clippy::range_minus_one)]
+    RangeInclusive::new(el0_rw_start(), el0_rw_end_exclusive().wrapping_sub(1))
+}
+
+/// Guard page of the stack of core `CORE`, as a function without arguments for the descriptor.
+fn stack_guard_range_inclusive<const CORE: usize>() -> RangeInclusive<usize> {
+    let guard = memory::core_stack_guard_range(CORE);
+
+    // Notice the subtraction to turn the exclusive end into an inclusive end.
+    #[allow(clippy::range_minus_one)]
+    RangeInclusive::new(
+        memory::virt_to_phys(guard.start as usize),
+        memory::virt_to_phys(guard.end as usize) - 1,
+    )
+}
+
+/// Sets the MMIO range to span all `ranges` of the `/soc` node, which map the peripheral buses to
+/// CPU addresses. Keeps the default range if the device tree is not available.
+///
+/// The range is computed once, because the device tree is parsed with the MMU and caches off.
+fn init_mmio_range() {
+    let soc = match dtb::fdt().and_then(|fdt| fdt.find_node("/soc")) {
+        Some(soc) => soc,
+        None => return,
+    };
+
+    let (start, end_inclusive) =
+        soc.ranges()
+            .filter(|r| r.size > 0)
+            .fold((u64::MAX, 0), |(start, end), r| {
+                (
+                    start.min(r.parent_address),
+                    end.max(r.parent_address + (r.size - 1)),
+                )
+            });
+
+    if start <= end_inclusive && end_inclusive <= LOW_ADDR_SPACE_END_INCLUSIVE as u64 {
+        MMIO_START.store(start as usize, Ordering::Relaxed);
+        MMIO_END_INCLUSIVE.store(end_inclusive as usize, Ordering::Relaxed);
+    }
+}
+
 fn mmio_range_inclusive() -> RangeInclusive<usize> {
-    // Todo unhardcode
-    RangeInclusive::new(0xFE00_0000, 0xFF84_FFFF)
+    RangeInclusive::new(
+        MMIO_START.load(Ordering::Relaxed),
+        MMIO_END_INCLUSIVE.load(Ordering::Relaxed),
+    )
+}
+
+/// Sets the high MMIO range to span all `ranges` of the `/scb` node, which lie above the 32 bit
+/// address space, such as the PCIe outbound window. Keeps the default range if the device tree is
+/// not available.
+fn init_high_mmio_range() {
+    let fdt = match dtb::fdt() {
+        Some(fdt) => fdt,
+        None => return,
+    };
+
+    let (start, end_inclusive) = fdt
+        .find_node("/scb")
+        .into_iter()
+        .flat_map(|scb| scb.ranges())
+        .filter(|r| r.size > 0 && r.parent_address > LOW_ADDR_SPACE_END_INCLUSIVE as u64)
+        .fold((u64::MAX, 0), |(start, end), r| {
+            (
+                start.min(r.parent_address),
+                end.max(r.parent_address + (r.size - 1)),
+            )
+        });
+
+    // An empty range right above the 32 bit address space if there is no high MMIO.
+    let (start, end_inclusive) = if start <= end_inclusive {
+        (start as usize, end_inclusive as usize)
+    } else {
+        (
+            LOW_ADDR_SPACE_END_INCLUSIVE + 1,
+            LOW_ADDR_SPACE_END_INCLUSIVE,
+        )
+    };
+
+    HIGH_MMIO_START.store(start, Ordering::Relaxed);
+    HIGH_MMIO_END_INCLUSIVE.store(end_inclusive, Ordering::Relaxed);
+}
+
+fn high_mmio_range_inclusive() -> RangeInclusive<usize> {
+    RangeInclusive::new(
+        HIGH_MMIO_START.load(Ordering::Relaxed),
+        HIGH_MMIO_END_INCLUSIVE.load(Ordering::Relaxed),
+    )
+}
+
+/// Sets the end of RAM to the end of the highest `/memory` region. Keeps the default if the device
+/// tree is not available.
+fn init_ram_end() {
+    let end = dtb::fdt()
+        .into_iter()
+        .flat_map(|fdt| fdt.memory())
+        .map(|r| r.address + r.size)
+        .max()
+        .unwrap_or(0);
+
+    if end > 0 {
+        RAM_END_EXCLUSIVE.store(end as usize, Ordering::Relaxed);
+    }
+}
+
+/// The address space between the end of RAM and the high MMIO range, which has nothing behind it.
+fn unpopulated_range_inclusive() -> RangeInclusive<usize> {
+    let start = RAM_END_EXCLUSIVE
+        .load(Ordering::Relaxed)
+        .max(LOW_ADDR_SPACE_END_INCLUSIVE + 1);
+    let end_exclusive = HIGH_MMIO_START.load(Ordering::Relaxed).max(start);
+
+    // Notice the subtraction to turn the exclusive end into an inclusive end. The range is empty
+    // if there is no hole.
+    #[allow(clippy::range_minus_one)]
+    RangeInclusive::new(start, end_exclusive - 1)
+}
+
+/// The last address of the layout, covering the 32 bit address space, RAM and high MMIO.
+fn max_virt_addr_inclusive() -> usize {
+    LOW_ADDR_SPACE_END_INCLUSIVE
+        .max(RAM_END_EXCLUSIVE.load(Ordering::Relaxed) - 1)
+        .max(HIGH_MMIO_END_INCLUSIVE.load(Ordering::Relaxed))
 }
 
 // Symbols from the linker script.
 extern "Rust" {
     static __rx_start: UnsafeCell<()>;
     static __rx_end_exclusive: UnsafeCell<()>;
+    static __el0_rx_start: UnsafeCell<()>;
+    static __el0_rx_end_exclusive: UnsafeCell<()>;
+    static __el0_rw_start: UnsafeCell<()>;
+    static __el0_rw_end_exclusive: UnsafeCell<()>;
 }
 
-/// Start address of the Read+Execute (RX) range.
+/// Physical start address of the Read+Execute (RX) range.
 #[inline(always)]
 fn rx_start() -> usize {
-    unsafe { __rx_start.get() as usize }
+    unsafe { memory::virt_to_phys(__rx_start.get() as usize) }
 }
 
-/// Exclusive end address of the Read+Execute (RX) range.
+/// Physical exclusive end address of the Read+Execute (RX) range.
 #[inline(always)]
 fn rx_end_exclusive() -> usize {
-    unsafe { __rx_end_exclusive.get() as usize }
+    unsafe { memory::virt_to_phys(__rx_end_exclusive.get() as usize) }
+}
+
+/// Physical start address of the EL0 Read+Execute range.
+#[inline(always)]
+fn el0_rx_start() -> usize {
+    unsafe { memory::virt_to_phys(__el0_rx_start.get() as usize) }
+}
+
+/// Physical exclusive end address of the EL0 Read+Execute range.
+#[inline(always)]
+fn el0_rx_end_exclusive() -> usize {
+    unsafe { memory::virt_to_phys(__el0_rx_end_exclusive.get() as usize) }
+}
+
+/// Physical start address of the EL0 Read+Write range.
+#[inline(always)]
+fn el0_rw_start() -> usize {
+    unsafe { memory::virt_to_phys(__el0_rw_start.get() as usize) }
+}
+
+/// Physical exclusive end address of the EL0 Read+Write range.
+#[inline(always)]
+fn el0_rw_end_exclusive() -> usize {
+    unsafe { memory::virt_to_phys(__el0_rw_end_exclusive.get() as usize) }
 }
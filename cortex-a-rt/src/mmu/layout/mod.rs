@@ -1,13 +1,34 @@
-use super::AttributeFields;
+use core::ops::RangeInclusive;
 
+use super::{AttributeFields, LayoutError};
+
+#[cfg(target_arch = "aarch64")]
 pub mod default;
 pub mod simple;
+pub mod task;
 
 pub trait VirtualMemoryLayout {
+    /// The last (inclusive) virtual address of the layout, which determines the size of the
+    /// translated address space.
+    fn max_virt_addr_inclusive(&self) -> usize;
+
     /// For a virtual address, find and return the physical output address and corresponding
-    /// attributes.
+    /// attributes. Returns `None` if the address must be left unmapped.
     fn virt_addr_properties(
         &self,
         virt_addr: usize,
-    ) -> Result<(usize, AttributeFields), &'static str>;
+    ) -> Result<Option<(usize, AttributeFields)>, &'static str>;
+
+    /// Returns whether all addresses of the range share one translation and attributes, so that
+    /// they can be translated by a single block descriptor. Layouts, which can't tell, return
+    /// `false` and are translated page by page.
+    fn is_uniform(&self, _virt_range: RangeInclusive<usize>) -> bool {
+        false
+    }
+
+    /// Checks the layout for inconsistencies, before the translation tables are populated from
+    /// it. Layouts, which can't be inconsistent, keep the default.
+    fn validate(&self) -> Result<(), LayoutError> {
+        Ok(())
+    }
 }
@@ -1,6 +1,6 @@
-use core::fmt;
+use core::{fmt, ops::RangeInclusive};
 
-use crate::mmu::{AttributeFields, Translation, TranslationDescriptor};
+use crate::mmu::{AttributeFields, Granule, LayoutError, Translation, TranslationDescriptor};
 
 use super::VirtualMemoryLayout;
 
@@ -26,12 +26,16 @@ impl<const NUM_SPECIAL_RANGES: usize> SimpleMemoryLayout<{ NUM_SPECIAL_RANGES }>
 impl<const NUM_SPECIAL_RANGES: usize> VirtualMemoryLayout
     for SimpleMemoryLayout<{ NUM_SPECIAL_RANGES }>
 {
+    fn max_virt_addr_inclusive(&self) -> usize {
+        self.max_virt_addr_inclusive
+    }
+
     /// If the address is not found in `inner`, return an identity mapped default with normal
     /// cacheable DRAM attributes.
     fn virt_addr_properties(
         &self,
         virt_addr: usize,
-    ) -> Result<(usize, AttributeFields), &'static str> {
+    ) -> Result<Option<(usize, AttributeFields)>, &'static str> {
         if virt_addr > self.max_virt_addr_inclusive {
             return Err("Address out of range");
         }
@@ -41,13 +45,81 @@ impl<const NUM_SPECIAL_RANGES: usize> VirtualMemoryLayout
                 let output_addr = match i.physical_range_translation {
                     Translation::Identity => virt_addr,
                     Translation::Offset(a) => a + (virt_addr - (i.virtual_range)().start()),
+                    Translation::Unmapped => return Ok(None),
                 };
 
-                return Ok((output_addr, i.attribute_fields));
+                return Ok(Some((output_addr, i.attribute_fields)));
             }
         }
 
-        Ok((virt_addr, AttributeFields::default()))
+        Ok(Some((virt_addr, AttributeFields::default())))
+    }
+
+    /// The range is uniform if the first descriptor intersecting it covers it completely, or if no
+    /// descriptor intersects it at all.
+    fn is_uniform(&self, virt_range: RangeInclusive<usize>) -> bool {
+        if *virt_range.end() > self.max_virt_addr_inclusive {
+            return false;
+        }
+
+        for i in self.inner.iter() {
+            let range = (i.virtual_range)();
+
+            if range.is_empty()
+                || range.start() > virt_range.end()
+                || range.end() < virt_range.start()
+            {
+                continue;
+            }
+
+            return range.start() <= virt_range.start() && virt_range.end() <= range.end();
+        }
+
+        true
+    }
+
+    /// Every address must be described by at most one descriptor, because only the first one is
+    /// used. The ranges and output addresses must be aligned to the granule, which is the smallest
+    /// unit the translation tables can map, and must lie within the address space. Empty ranges
+    /// describe nothing and are skipped.
+    fn validate(&self) -> Result<(), LayoutError> {
+        let granule_mask = Granule::SIZE - 1;
+
+        for (n, i) in self.inner.iter().enumerate() {
+            let range = (i.virtual_range)();
+
+            if range.is_empty() {
+                continue;
+            }
+
+            if *range.end() > self.max_virt_addr_inclusive {
+                return Err(LayoutError::OutOfRange(i.name));
+            }
+
+            let output_start = match i.physical_range_translation {
+                Translation::Offset(a) => a,
+                Translation::Identity | Translation::Unmapped => *range.start(),
+            };
+
+            if (range.start() | output_start) & granule_mask != 0
+                || range.end() & granule_mask != granule_mask
+            {
+                return Err(LayoutError::Misaligned(i.name));
+            }
+
+            for other in self.inner[n + 1..].iter() {
+                let other_range = (other.virtual_range)();
+
+                if !other_range.is_empty()
+                    && range.start() <= other_range.end()
+                    && other_range.start() <= range.end()
+                {
+                    return Err(LayoutError::Overlap(i.name, other.name));
+                }
+            }
+        }
+
+        Ok(())
     }
 }
 
@@ -60,3 +132,193 @@ impl<const NUM_SPECIAL_RANGES: usize> fmt::Display for SimpleMemoryLayout<{ NUM_
         Ok(())
     }
 }
+
+//--------------------------------------------------------------------------------------------------
+// Testing
+//--------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mmu::{AccessPermissions, MemAttributes};
+
+    fn device() -> AttributeFields {
+        AttributeFields {
+            mem_attributes: MemAttributes::Device,
+            acc_perms: AccessPermissions::ReadWrite,
+            execute_never: true,
+            el0_access: false,
+            el0_execute_never: true,
+        }
+    }
+
+    fn descriptor(
+        name: &'static str,
+        virtual_range: fn() -> RangeInclusive<usize>,
+        physical_range_translation: Translation,
+    ) -> TranslationDescriptor {
+        TranslationDescriptor {
+            name,
+            virtual_range,
+            physical_range_translation,
+            attribute_fields: device(),
+        }
+    }
+
+    fn sample_layout() -> SimpleMemoryLayout<3> {
+        SimpleMemoryLayout::new(
+            0xFFFF_FFFF,
+            [
+                descriptor("Guard", || 0x7_0000..=0x7_FFFF, Translation::Unmapped),
+                descriptor(
+                    "Remapped",
+                    || 0x1000_0000..=0x1001_FFFF,
+                    Translation::Offset(0x8000_0000),
+                ),
+                descriptor("MMIO", || 0xFC00_0000..=0xFFFF_FFFF, Translation::Identity),
+            ],
+        )
+    }
+
+    #[test]
+    fn undescribed_addresses_are_identity_mapped_dram() {
+        let (phys_addr, attribute_fields) = sample_layout()
+            .virt_addr_properties(0x4000_1234)
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(phys_addr, 0x4000_1234);
+        assert!(attribute_fields == AttributeFields::default());
+    }
+
+    #[test]
+    fn descriptor_translations() {
+        let layout = sample_layout();
+
+        assert!(layout.virt_addr_properties(0x7_8000).unwrap().is_none());
+
+        let (phys_addr, attribute_fields) =
+            layout.virt_addr_properties(0x1001_0040).unwrap().unwrap();
+        assert_eq!(phys_addr, 0x8001_0040);
+        assert!(attribute_fields == device());
+
+        let (phys_addr, attribute_fields) =
+            layout.virt_addr_properties(0xFE20_0000).unwrap().unwrap();
+        assert_eq!(phys_addr, 0xFE20_0000);
+        assert!(attribute_fields == device());
+    }
+
+    #[test]
+    fn address_beyond_layout_is_rejected() {
+        let layout = sample_layout();
+
+        assert!(layout.virt_addr_properties(0xFFFF_FFFF).is_ok());
+        assert!(layout.virt_addr_properties(0x1_0000_0000).is_err());
+        assert!(!layout.is_uniform(0xC000_0000..=0x1_3FFF_FFFF));
+    }
+
+    #[test]
+    fn uniform_ranges() {
+        let layout = sample_layout();
+
+        // No descriptor.
+        assert!(layout.is_uniform(0x4000_0000..=0x7FFF_FFFF));
+        // Within one descriptor.
+        assert!(layout.is_uniform(0xFE00_0000..=0xFE1F_FFFF));
+        // Partly covered by a descriptor.
+        assert!(!layout.is_uniform(0xE000_0000..=0xFFFF_FFFF));
+        assert!(!layout.is_uniform(0..=0x1F_FFFF));
+    }
+
+    #[test]
+    fn valid_layout() {
+        assert!(sample_layout().validate().is_ok());
+
+        // Empty ranges are skipped, even if they would overlap or be misaligned.
+        #[allow(clippy::reversed_empty_ranges)]
+        let layout = SimpleMemoryLayout::new(
+            0xFFFF_FFFF,
+            [
+                descriptor("MMIO", || 0xFC00_0000..=0xFFFF_FFFF, Translation::Identity),
+                descriptor("Empty", || 0xFC00_0001..=0xFC00_0000, Translation::Identity),
+            ],
+        );
+        assert!(layout.validate().is_ok());
+    }
+
+    #[test]
+    fn overlapping_descriptors() {
+        let layout = SimpleMemoryLayout::new(
+            0xFFFF_FFFF,
+            [
+                descriptor("A", || 0x10_0000..=0x1F_FFFF, Translation::Identity),
+                descriptor("B", || 0x30_0000..=0x3F_FFFF, Translation::Identity),
+                descriptor("C", || 0x1F_0000..=0x2F_FFFF, Translation::Identity),
+            ],
+        );
+
+        assert!(matches!(
+            layout.validate(),
+            Err(LayoutError::Overlap("A", "C"))
+        ));
+    }
+
+    #[test]
+    fn misaligned_descriptors() {
+        let layout = SimpleMemoryLayout::new(
+            0xFFFF_FFFF,
+            [descriptor(
+                "Start",
+                || 0x10_0800..=0x1F_FFFF,
+                Translation::Identity,
+            )],
+        );
+        assert!(matches!(
+            layout.validate(),
+            Err(LayoutError::Misaligned("Start"))
+        ));
+
+        let layout = SimpleMemoryLayout::new(
+            0xFFFF_FFFF,
+            [descriptor(
+                "End",
+                || 0x10_0000..=0x1F_F7FF,
+                Translation::Identity,
+            )],
+        );
+        assert!(matches!(
+            layout.validate(),
+            Err(LayoutError::Misaligned("End"))
+        ));
+
+        let layout = SimpleMemoryLayout::new(
+            0xFFFF_FFFF,
+            [descriptor(
+                "Output",
+                || 0x10_0000..=0x1F_FFFF,
+                Translation::Offset(0x800),
+            )],
+        );
+        assert!(matches!(
+            layout.validate(),
+            Err(LayoutError::Misaligned("Output"))
+        ));
+    }
+
+    #[test]
+    fn descriptor_beyond_layout() {
+        let layout = SimpleMemoryLayout::new(
+            0xFFFF_FFFF,
+            [descriptor(
+                "High",
+                || 0xFFFF_0000..=0x1_0000_FFFF,
+                Translation::Identity,
+            )],
+        );
+
+        assert!(matches!(
+            layout.validate(),
+            Err(LayoutError::OutOfRange("High"))
+        ));
+    }
+}
@@ -0,0 +1,184 @@
+use core::ops::{Range, RangeInclusive};
+
+use crate::mmu::{AccessPermissions, AttributeFields, Granule, LayoutError, MemAttributes};
+
+use super::VirtualMemoryLayout;
+
+/// Attributes of the code of a task, which only EL0 may execute.
+const CODE_ATTRIBUTES: AttributeFields = AttributeFields {
+    mem_attributes: MemAttributes::CacheableDRAM,
+    acc_perms: AccessPermissions::ReadOnly,
+    execute_never: true,
+    el0_access: true,
+    el0_execute_never: false,
+};
+
+/// Attributes of the data and stack of a task.
+const DATA_ATTRIBUTES: AttributeFields = AttributeFields {
+    mem_attributes: MemAttributes::CacheableDRAM,
+    acc_perms: AccessPermissions::ReadWrite,
+    execute_never: true,
+    el0_access: true,
+    el0_execute_never: true,
+};
+
+/// Memory layout of a single EL0 task, which identity maps the code, data and stack of the task
+/// accessible from EL0 and leaves everything else unmapped.
+pub struct TaskLayout {
+    /// Name, physical range and attributes of each region of the task.
+    regions: [(&'static str, Range<usize>, AttributeFields); 3],
+}
+
+impl TaskLayout {
+    /// Create a new instance from the physical ranges of the task's memory. Empty ranges are
+    /// skipped.
+    pub const fn new(code: Range<usize>, data: Range<usize>, stack: Range<usize>) -> Self {
+        Self {
+            regions: [
+                ("Task code", code, CODE_ATTRIBUTES),
+                ("Task data", data, DATA_ATTRIBUTES),
+                ("Task stack", stack, DATA_ATTRIBUTES),
+            ],
+        }
+    }
+
+    /// The regions, which are not empty.
+    fn regions(&self) -> impl Iterator<Item = &(&'static str, Range<usize>, AttributeFields)> {
+        self.regions
+            .iter()
+            .filter(|(_, range, _)| !range.is_empty())
+    }
+}
+
+impl VirtualMemoryLayout for TaskLayout {
+    /// The address space ends with the last region of the task.
+    fn max_virt_addr_inclusive(&self) -> usize {
+        self.regions()
+            .map(|(_, range, _)| range.end - 1)
+            .max()
+            .unwrap_or(0)
+    }
+
+    fn virt_addr_properties(
+        &self,
+        virt_addr: usize,
+    ) -> Result<Option<(usize, AttributeFields)>, &'static str> {
+        if virt_addr > self.max_virt_addr_inclusive() {
+            return Err("Address out of range");
+        }
+
+        Ok(self
+            .regions()
+            .find(|(_, range, _)| range.contains(&virt_addr))
+            .map(|(_, _, attribute_fields)| (virt_addr, *attribute_fields)))
+    }
+
+    /// The range is uniform if a region covers it completely, or if no region intersects it.
+    fn is_uniform(&self, virt_range: RangeInclusive<usize>) -> bool {
+        if *virt_range.end() > self.max_virt_addr_inclusive() {
+            return false;
+        }
+
+        match self.regions().find(|(_, range, _)| {
+            range.start <= *virt_range.end() && *virt_range.start() < range.end
+        }) {
+            Some((_, range, _)) => {
+                range.start <= *virt_range.start() && *virt_range.end() < range.end
+            }
+            None => true,
+        }
+    }
+
+    /// The regions must be aligned to the granule, so that no page is shared with memory outside
+    /// of the task, and must not overlap.
+    fn validate(&self) -> Result<(), LayoutError> {
+        let granule_mask = Granule::SIZE - 1;
+
+        for (n, &(name, ref range, _)) in self.regions().enumerate() {
+            if (range.start | range.end) & granule_mask != 0 {
+                return Err(LayoutError::Misaligned(name));
+            }
+
+            for &(other_name, ref other_range, _) in self.regions().skip(n + 1) {
+                if range.start < other_range.end && other_range.start < range.end {
+                    return Err(LayoutError::Overlap(name, other_name));
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PAGE: usize = Granule::SIZE;
+
+    fn sample_layout() -> TaskLayout {
+        TaskLayout::new(
+            0x10_0000..0x10_0000 + PAGE,
+            0x20_0000..0x20_0000 + 2 * PAGE,
+            0x30_0000..0x30_0000 + PAGE,
+        )
+    }
+
+    #[test]
+    fn only_task_memory_is_mapped() {
+        let layout = sample_layout();
+
+        assert_eq!(layout.max_virt_addr_inclusive(), 0x30_0000 + PAGE - 1);
+        assert!(layout.validate().is_ok());
+
+        let (phys_addr, attribute_fields) =
+            layout.virt_addr_properties(0x10_0040).unwrap().unwrap();
+        assert_eq!(phys_addr, 0x10_0040);
+        assert!(attribute_fields == CODE_ATTRIBUTES);
+
+        let (_, attribute_fields) = layout
+            .virt_addr_properties(0x20_0000 + PAGE)
+            .unwrap()
+            .unwrap();
+        assert!(attribute_fields == DATA_ATTRIBUTES);
+
+        assert!(layout.virt_addr_properties(0).unwrap().is_none());
+        assert!(layout
+            .virt_addr_properties(0x10_0000 + PAGE)
+            .unwrap()
+            .is_none());
+        assert!(layout.virt_addr_properties(0x30_0000 + PAGE).is_err());
+    }
+
+    #[test]
+    fn uniform_ranges() {
+        let layout = sample_layout();
+
+        // No region.
+        assert!(layout.is_uniform(0..=0xF_FFFF));
+        // Within one region.
+        assert!(layout.is_uniform(0x20_0000..=0x20_0000 + PAGE - 1));
+        // Partly covered by a region.
+        assert!(!layout.is_uniform(0..=0x1F_FFFF));
+        assert!(!layout.is_uniform(0x20_0000..=0x20_0000 + 3 * PAGE - 1));
+    }
+
+    #[test]
+    fn invalid_layouts() {
+        let misaligned = TaskLayout::new(0x10_0000..0x10_0100, 0..0, 0x30_0000..0x30_0000 + PAGE);
+        assert!(matches!(
+            misaligned.validate(),
+            Err(LayoutError::Misaligned("Task code"))
+        ));
+
+        let overlapping = TaskLayout::new(
+            0x10_0000..0x10_0000 + PAGE,
+            0x20_0000..0x20_0000 + 2 * PAGE,
+            0x20_0000 + PAGE..0x20_0000 + 3 * PAGE,
+        );
+        assert!(matches!(
+            overlapping.validate(),
+            Err(LayoutError::Overlap("Task data", "Task stack"))
+        ));
+    }
+}
@@ -4,7 +4,13 @@
 
 //! Memory Management Unit Driver.
 //!
-//! Only 64 KiB granule is supported.
+//! The 64 KiB granule is used, unless the `granule-4k` feature selects the 4 KiB granule. With the
+//! `hypervisor` feature, the MMU of the EL2 translation regime is used instead of EL1.
+//!
+//! With the `higher-half` feature, the translation tables are installed in TTBR1_EL1 and translate
+//! the higher half starting at `KERNEL_VIRT_OFFSET`, while TTBR0_EL1 is left to per-task tables
+//! installed with `set_user_table()`. `boot.s` has already switched on the MMU with its boot
+//! translation table, which `enable_mmu_and_caching()` replaces.
 //!
 //! # Orientation
 //!
@@ -14,20 +20,52 @@
 //! crate::memory::mmu::arch_mmu
 
 use cortex_a::{barrier, regs::*};
+use register::InMemoryRegister;
+
+use core::{fmt, ops::Range};
 
 use super::{
-    layout::{default::DefaultAddrSpace, VirtualMemoryLayout},
-    translation_table::FixedSizeTranslationTable,
-    AddressSpace, MMUEnableError, TranslationGranule,
+    layout::VirtualMemoryLayout,
+    translation_table::{
+        mair, FixedSizeTranslationTable, MappedRange, KERNEL_VIRT_OFFSET, OUT_OF_TABLES,
+    },
+    AttributeFields, Granule, MMUEnableError, MapError, TranslateError,
 };
 
 pub struct MemoryManagementUnit;
 
-pub type Granule512MiB = TranslationGranule<{ 512 * 1024 * 1024 }>;
-pub type Granule64KiB = TranslationGranule<{ 64 * 1024 }>;
+/// Human-readable dump of the live translation tables, returned by `MemoryManagementUnit::dump()`.
+pub struct TranslationTableDump;
+
+/// Number of tables, including the root table. Each further table translates a window, which is
+/// not covered by a block descriptor. With the 64 KiB granule, the default layout needs level 3
+/// tables for the kernel and for the boundary between RAM and MMIO. The rest is left for `map()`,
+/// `unmap()` and `protect()`, which take a table for each 512 MiB block they split.
+#[cfg(not(feature = "granule-4k"))]
+const NUM_TABLES: usize = 16;
+
+/// With the 4 KiB granule, the default layout needs a level 1 table, level 2 tables for the kernel
+/// and for the boundary between RAM and MMIO, and level 3 tables for the 2 MiB windows, which are
+/// not uniform. Splitting a 1 GiB block takes a level 2 table and a level 3 table for each 2 MiB
+/// window of the changed range, splitting a 2 MiB block takes a level 3 table.
+#[cfg(feature = "granule-4k")]
+const NUM_TABLES: usize = 64;
+
+pub type TranslationTable = FixedSizeTranslationTable<NUM_TABLES, KERNEL_VIRT_OFFSET>;
+
+/// Number of tables of a task translation table, including the root table. With the 64 KiB
+/// granule, a level 3 table is needed for each 512 MiB window containing memory of the task.
+#[cfg(all(feature = "higher-half", not(feature = "granule-4k")))]
+const TASK_NUM_TABLES: usize = 4;
+
+/// With the 4 KiB granule, a level 1 table, a level 2 table for each 1 GiB window and a level 3
+/// table for each 2 MiB window containing memory of the task are needed.
+#[cfg(all(feature = "higher-half", feature = "granule-4k"))]
+const TASK_NUM_TABLES: usize = 8;
 
-const NUM_LVL2_TABLES: usize = DefaultAddrSpace::SIZE >> Granule512MiB::SHIFT;
-pub type TranslationTable = FixedSizeTranslationTable<NUM_LVL2_TABLES>;
+/// Translation table of an EL0 task, see `set_user_table()` and the `el0` module.
+#[cfg(feature = "higher-half")]
+pub type TaskTranslationTable = FixedSizeTranslationTable<TASK_NUM_TABLES, KERNEL_VIRT_OFFSET>;
 
 /// The translation table.
 ///
@@ -38,23 +76,27 @@ static mut TRANSLATION_TABLE: TranslationTable = TranslationTable::new();
 
 static MMU: MemoryManagementUnit = MemoryManagementUnit;
 
-impl<const AS_SIZE: usize> AddressSpace<AS_SIZE> {
-    /// Checks for architectural restrictions.
-    pub const fn arch_address_space_size_sanity_checks() {
-        // Size must be at least one full 512 MiB table.
-        assert!((AS_SIZE % Granule512MiB::SIZE) == 0);
+// SCTLR_EL2 bits, which are at the same positions as in SCTLR_EL1.
+#[cfg(feature = "hypervisor")]
+const SCTLR_ELX_M: u64 = 1 << 0;
+#[cfg(feature = "hypervisor")]
+const SCTLR_ELX_C: u64 = 1 << 2;
+#[cfg(feature = "hypervisor")]
+const SCTLR_ELX_I: u64 = 1 << 12;
 
-        // Check for 48 bit virtual address size as maximum, which is supported by any ARMv8
-        // version.
-        assert!(AS_SIZE <= (1 << 48));
-    }
+// Provided by boot.s.
+#[cfg(feature = "higher-half")]
+extern "C" {
+    fn __switch_kernel_table(ttbr1: u64);
 }
 
 impl MemoryManagementUnit {
-    /// Setup function for the MAIR_EL1 register.
-    fn set_up_mair(&self) {
+    /// Value of the MAIR_EL1 register, which has the same layout as MAIR_EL2.
+    fn mair_value(&self) -> u64 {
+        let mair = InMemoryRegister::<u64, MAIR_EL1::Register>::new(0);
+
         // Define the memory types being mapped.
-        MAIR_EL1.write(
+        mair.write(
             // Attribute 1 - Cacheable normal DRAM.
             MAIR_EL1::Attr1_Normal_Outer::WriteBack_NonTransient_ReadWriteAlloc +
         MAIR_EL1::Attr1_Normal_Inner::WriteBack_NonTransient_ReadWriteAlloc +
@@ -62,16 +104,35 @@ impl MemoryManagementUnit {
         // Attribute 0 - Device.
         MAIR_EL1::Attr0_Device::nonGathering_nonReordering_EarlyWriteAck,
         );
+
+        // The remaining attributes, encoded as per ARMv8-A Architecture Reference Manual section
+        // D13.2.97.
+        //
+        // Outer and inner non-cacheable.
+        const NORMAL_NON_CACHEABLE: u64 = 0x44;
+        // Outer and inner write-through non-transient, read and write allocate.
+        const NORMAL_WRITE_THROUGH: u64 = 0xBB;
+        // Device-nGnRnE.
+        const DEVICE_NGNRNE: u64 = 0x00;
+
+        mair.get()
+            | (NORMAL_NON_CACHEABLE << (8 * mair::NORMAL_NON_CACHEABLE))
+            | (NORMAL_WRITE_THROUGH << (8 * mair::NORMAL_WRITE_THROUGH))
+            | (DEVICE_NGNRNE << (8 * mair::DEVICE_NGNRNE))
     }
 
-    /// Configure various settings of stage 1 of the EL1 translation regime.
-    fn configure_translation_control(&self) {
-        let t0sz = (64 - DefaultAddrSpace::SIZE_SHIFT) as u64;
+    /// Value of the TCR_EL1 register for stage 1 of the EL1 translation regime. T0SZ is derived
+    /// from the populated translation table.
+    #[cfg(not(feature = "higher-half"))]
+    fn tcr_value(&self) -> u64 {
+        let addr_space_size = unsafe { TRANSLATION_TABLE.addr_space_size() };
+        let t0sz = (64 - addr_space_size.trailing_zeros()) as u64;
+        let tcr = InMemoryRegister::<u64, TCR_EL1::Register>::new(0);
 
-        TCR_EL1.write(
+        tcr.write(
             TCR_EL1::TBI0::Used
-                + TCR_EL1::IPS::Bits_40
-                + TCR_EL1::TG0::KiB_64
+                + TCR_EL1::IPS.val(phys_addr_range())
+                + Self::tcr_tg0()
                 + TCR_EL1::SH0::Inner
                 + TCR_EL1::ORGN0::WriteBack_ReadAlloc_WriteAlloc_Cacheable
                 + TCR_EL1::IRGN0::WriteBack_ReadAlloc_WriteAlloc_Cacheable
@@ -80,33 +141,146 @@ impl MemoryManagementUnit {
                 + TCR_EL1::T0SZ.val(t0sz)
                 + TCR_EL1::EPD1::DisableTTBR1Walks,
         );
+
+        tcr.get()
     }
 
-    pub unsafe fn enable_mmu_and_caching(
-        &self,
-        layout: &impl VirtualMemoryLayout,
-    ) -> Result<(), MMUEnableError> {
-        if self.is_enabled() {
+    /// Value of the TCR_EL1 register with the `higher-half` feature. TTBR1_EL1 translates the range
+    /// of the root table, which starts at `KERNEL_VIRT_OFFSET`. TTBR0_EL1 walks stay enabled until
+    /// the boot translation table is replaced.
+    #[cfg(feature = "higher-half")]
+    fn tcr_value(&self) -> u64 {
+        let addr_space_size = unsafe { TRANSLATION_TABLE.addr_space_size() };
+        let t0sz = (64 - addr_space_size.trailing_zeros()) as u64;
+        let t1sz = KERNEL_VIRT_OFFSET.leading_ones() as u64;
+        let tcr = InMemoryRegister::<u64, TCR_EL1::Register>::new(0);
+
+        tcr.write(
+            TCR_EL1::TBI0::Used
+                + TCR_EL1::IPS.val(phys_addr_range())
+                + Self::tcr_tg0()
+                + TCR_EL1::SH0::Inner
+                + TCR_EL1::ORGN0::WriteBack_ReadAlloc_WriteAlloc_Cacheable
+                + TCR_EL1::IRGN0::WriteBack_ReadAlloc_WriteAlloc_Cacheable
+                + TCR_EL1::EPD0::EnableTTBR0Walks
+                + TCR_EL1::A1::TTBR0
+                + TCR_EL1::T0SZ.val(t0sz)
+                + Self::tcr_tg1()
+                + TCR_EL1::SH1::Inner
+                + TCR_EL1::ORGN1::WriteBack_ReadAlloc_WriteAlloc_Cacheable
+                + TCR_EL1::IRGN1::WriteBack_ReadAlloc_WriteAlloc_Cacheable
+                + TCR_EL1::T1SZ.val(t1sz)
+                + TCR_EL1::EPD1::EnableTTBR1Walks,
+        );
+
+        tcr.get()
+    }
+
+    /// TCR_EL1.TG1 value of the granule, which is encoded differently from TG0.
+    #[cfg(all(feature = "higher-half", not(feature = "granule-4k")))]
+    fn tcr_tg1() -> register::FieldValue<u64, TCR_EL1::Register> {
+        TCR_EL1::TG1::KiB_64
+    }
+
+    /// TCR_EL1.TG1 value of the granule, which is encoded differently from TG0.
+    #[cfg(all(feature = "higher-half", feature = "granule-4k"))]
+    fn tcr_tg1() -> register::FieldValue<u64, TCR_EL1::Register> {
+        TCR_EL1::TG1::KiB_4
+    }
+
+    /// TCR_EL1.TG0 value of the granule. TCR_EL2 and VTCR_EL2 use the same encoding.
+    #[cfg(not(feature = "granule-4k"))]
+    fn tcr_tg0() -> register::FieldValue<u64, TCR_EL1::Register> {
+        TCR_EL1::TG0::KiB_64
+    }
+
+    /// TCR_EL1.TG0 value of the granule. TCR_EL2 and VTCR_EL2 use the same encoding.
+    #[cfg(feature = "granule-4k")]
+    fn tcr_tg0() -> register::FieldValue<u64, TCR_EL1::Register> {
+        TCR_EL1::TG0::KiB_4
+    }
+
+    /// Setup function for the MAIR_EL1 register.
+    #[cfg(not(feature = "hypervisor"))]
+    fn set_up_mair(&self) {
+        MAIR_EL1.set(self.mair_value());
+    }
+
+    /// Configure various settings of stage 1 of the EL1 translation regime.
+    #[cfg(not(feature = "hypervisor"))]
+    fn configure_translation_control(&self) {
+        TCR_EL1.set(self.tcr_value());
+    }
+
+    /// Setup function for the MAIR_EL2 register.
+    #[cfg(feature = "hypervisor")]
+    fn set_up_mair(&self) {
+        unsafe {
+            asm!("msr MAIR_EL2, {}", in(reg) self.mair_value(), options(nomem, nostack));
+        }
+    }
+
+    /// Configure various settings of the EL2 translation regime.
+    #[cfg(feature = "hypervisor")]
+    fn configure_translation_control(&self) {
+        // T0SZ, IRGN0, ORGN0, SH0 and TG0 are at the same positions as in TCR_EL1.
+        const TCR_EL1_TTBR0_FIELDS: u64 = 0xFFFF;
+
+        // RES1 bits [31] and [23], physical address size in PS [18:16].
+        const TCR_EL2_RES1: u64 = (1 << 31) | (1 << 23);
+        const TCR_EL2_PS_SHIFT: u64 = 16;
+
+        let tcr = (self.tcr_value() & TCR_EL1_TTBR0_FIELDS)
+            | TCR_EL2_RES1
+            | (phys_addr_range() << TCR_EL2_PS_SHIFT);
+
+        unsafe {
+            asm!("msr TCR_EL2, {}", in(reg) tcr, options(nomem, nostack));
+        }
+    }
+
+    /// Checks whether the MMU can be enabled on the executing core.
+    fn check_enable_preconditions(&self) -> Result<(), MMUEnableError> {
+        if self.is_enabled() && !cfg!(feature = "higher-half") {
+            return Err(MMUEnableError::AlreadyEnabled);
+        }
+
+        // With the `higher-half` feature, the MMU is already on with the boot translation table.
+        #[cfg(feature = "higher-half")]
+        if self.is_kernel_table_installed() {
             return Err(MMUEnableError::AlreadyEnabled);
         }
 
         // Fail early if translation granule is not supported.
-        if !ID_AA64MMFR0_EL1.matches_all(ID_AA64MMFR0_EL1::TGran64::Supported) {
+        #[cfg(not(feature = "granule-4k"))]
+        let granule_supported = ID_AA64MMFR0_EL1.matches_all(ID_AA64MMFR0_EL1::TGran64::Supported);
+        #[cfg(feature = "granule-4k")]
+        let granule_supported = ID_AA64MMFR0_EL1.matches_all(ID_AA64MMFR0_EL1::TGran4::Supported);
+
+        if !granule_supported {
             return Err(MMUEnableError::Other(
                 "Translation granule not supported in HW",
             ));
         }
 
+        Ok(())
+    }
+
+    /// Programs the executing core to use the populated translation tables and switches the MMU on.
+    #[cfg(not(feature = "higher-half"))]
+    unsafe fn enable(&self) {
         // Prepare the memory attribute indirection register.
         self.set_up_mair();
 
-        // Populate translation tables.
-        TRANSLATION_TABLE
-            .populate_tt_entries(layout)
-            .map_err(|e| MMUEnableError::Other(e))?;
-
         // Set the "Translation Table Base Register".
+        #[cfg(not(feature = "hypervisor"))]
         TTBR0_EL1.set_baddr(TRANSLATION_TABLE.phys_base_address());
+        #[cfg(feature = "hypervisor")]
+        asm!(
+            "msr TTBR0_EL2, {}",
+            in(reg) TRANSLATION_TABLE.phys_base_address(),
+            options(nomem, nostack)
+        );
 
         self.configure_translation_control();
 
@@ -116,18 +290,476 @@ impl MemoryManagementUnit {
         barrier::isb(barrier::SY);
 
         // Enable the MMU and turn on data and instruction caching.
+        #[cfg(not(feature = "hypervisor"))]
         SCTLR_EL1.modify(SCTLR_EL1::M::Enable + SCTLR_EL1::C::Cacheable + SCTLR_EL1::I::Cacheable);
+        #[cfg(feature = "hypervisor")]
+        asm!(
+            "mrs {tmp}, SCTLR_EL2",
+            "orr {tmp}, {tmp}, {bits}",
+            "msr SCTLR_EL2, {tmp}",
+            tmp = out(reg) _,
+            bits = in(reg) SCTLR_ELX_M | SCTLR_ELX_C | SCTLR_ELX_I,
+            options(nomem, nostack)
+        );
 
         // Force MMU init to complete before next instruction.
         barrier::isb(barrier::SY);
+    }
+
+    /// Replaces the boot translation table of the executing core by the populated translation
+    /// tables and leaves TTBR0_EL1 empty.
+    #[cfg(feature = "higher-half")]
+    unsafe fn enable(&self) {
+        // The attributes used by the boot translation table keep their indices.
+        self.set_up_mair();
+        self.configure_translation_control();
+        barrier::isb(barrier::SY);
+
+        // Called through the identity mapping of the boot translation table in TTBR0_EL1.
+        let switch_kernel_table: unsafe extern "C" fn(u64) = core::mem::transmute(
+            crate::memory::virt_to_phys(__switch_kernel_table as *const () as usize),
+        );
+        switch_kernel_table(TRANSLATION_TABLE.phys_base_address());
+
+        self.clear_user_table();
+    }
+
+    /// Returns whether the executing core translates the higher half with the populated
+    /// translation tables instead of the boot translation table.
+    #[cfg(feature = "higher-half")]
+    fn is_kernel_table_installed(&self) -> bool {
+        let ttbr1: u64;
+
+        unsafe {
+            asm!("mrs {}, TTBR1_EL1", out(reg) ttbr1, options(nomem, nostack, preserves_flags));
+        }
+
+        ttbr1 == unsafe { TRANSLATION_TABLE.phys_base_address() }
+    }
+
+    /// Installs the translation table of a task in TTBR0_EL1 of the executing core, so that it
+    /// translates the lower half. T0SZ is derived from the populated table.
+    ///
+    /// No ASIDs are used, so all TLB entries of the executing core are invalidated.
+    ///
+    /// # Safety
+    ///
+    /// - The table must be populated and must stay in place while it is installed.
+    /// - Only available after `enable_mmu_and_caching()` has replaced the boot translation table.
+    #[cfg(feature = "higher-half")]
+    pub unsafe fn set_user_table<const NUM_TABLES: usize>(
+        &self,
+        table: &FixedSizeTranslationTable<NUM_TABLES, KERNEL_VIRT_OFFSET>,
+    ) {
+        let t0sz = (64 - table.addr_space_size().trailing_zeros()) as u64;
+
+        TTBR0_EL1.set_baddr(table.phys_base_address());
+        TCR_EL1.modify(TCR_EL1::T0SZ.val(t0sz) + TCR_EL1::EPD0::EnableTTBR0Walks);
+
+        invalidate_tlb_local();
+    }
+
+    /// Removes the translation table of a task from TTBR0_EL1 of the executing core, so that any
+    /// access to the lower half faults.
+    ///
+    /// # Safety
+    ///
+    /// - See `set_user_table()`.
+    #[cfg(feature = "higher-half")]
+    pub unsafe fn clear_user_table(&self) {
+        TCR_EL1.modify(TCR_EL1::EPD0::DisableTTBR0Walks);
+        TTBR0_EL1.set(0);
+
+        invalidate_tlb_local();
+    }
+
+    pub unsafe fn enable_mmu_and_caching(
+        &self,
+        layout: &impl VirtualMemoryLayout,
+    ) -> Result<(), MMUEnableError> {
+        self.check_enable_preconditions()?;
+
+        layout.validate().map_err(MMUEnableError::InvalidLayout)?;
+
+        // Populate translation tables.
+        TRANSLATION_TABLE
+            .populate_tt_entries(layout)
+            .map_err(|e| MMUEnableError::Other(e))?;
+
+        self.enable();
 
         Ok(())
     }
 
+    /// Enables the MMU on a secondary core, reusing the translation tables populated by the boot
+    /// core.
+    ///
+    /// # Safety
+    ///
+    /// - `enable_mmu_and_caching()` must have completed on the boot core.
+    pub unsafe fn enable_mmu_and_caching_secondary(&self) -> Result<(), MMUEnableError> {
+        self.check_enable_preconditions()?;
+
+        self.enable();
+
+        Ok(())
+    }
+
+    /// Maps the virtual range to the physical range starting at `phys_start`.
+    ///
+    /// Pages, which are already mapped, are replaced following the break-before-make sequence. The
+    /// range must be aligned to the granule.
+    ///
+    /// Blocks containing the range are split into pages, and windows without a table get one.
+    /// The tables are taken from the fixed pool of `NUM_TABLES`, which is not refilled. If it has
+    /// too few tables left, `MapError::OutOfTables` is returned. All errors are detected before
+    /// anything is changed.
+    ///
+    /// # Safety
+    ///
+    /// - Changes the memory view of all cores. Each page is briefly unmapped, so the range must not
+    ///   be accessed concurrently and must not contain the executing code or stack. If the range
+    ///   lies within a block, the same applies to the whole block, which is split into pages.
+    /// - Calls to `map()`, `unmap()` and `protect()` must be serialized by the caller.
+    pub unsafe fn map(
+        &self,
+        virt_range: Range<usize>,
+        phys_start: usize,
+        attribute_fields: AttributeFields,
+    ) -> Result<(), MapError> {
+        Self::check_range(&virt_range, phys_start)?;
+        Self::check_tables(&virt_range, true)?;
+
+        let result = virt_range
+            .clone()
+            .step_by(Granule::SIZE)
+            .try_for_each(|virt_addr| {
+                let phys_addr = phys_start + (virt_addr - virt_range.start);
+
+                self.update_page(virt_addr, Some((phys_addr, attribute_fields)))
+            });
+
+        barrier::isb(barrier::SY);
+
+        result
+    }
+
+    /// Unmaps the virtual range, any later access to it faults. The range must be aligned to the
+    /// granule.
+    ///
+    /// Blocks containing the range are split into pages, see `map()`.
+    ///
+    /// # Safety
+    ///
+    /// - See `map()`.
+    pub unsafe fn unmap(&self, virt_range: Range<usize>) -> Result<(), MapError> {
+        Self::check_range(&virt_range, 0)?;
+        Self::check_tables(&virt_range, false)?;
+
+        let result = virt_range
+            .step_by(Granule::SIZE)
+            .try_for_each(|virt_addr| self.update_page(virt_addr, None));
+
+        barrier::isb(barrier::SY);
+
+        result
+    }
+
+    /// Changes the attributes of the virtual range, keeping its physical addresses. The whole range
+    /// must be mapped and aligned to the granule.
+    ///
+    /// Blocks containing the range are split into pages, see `map()`.
+    ///
+    /// # Safety
+    ///
+    /// - See `map()`.
+    pub unsafe fn protect(
+        &self,
+        virt_range: Range<usize>,
+        attribute_fields: AttributeFields,
+    ) -> Result<(), MapError> {
+        Self::check_range(&virt_range, 0)?;
+
+        // Fail before changing anything if part of the range is not mapped.
+        for virt_addr in virt_range.clone().step_by(Granule::SIZE) {
+            if Self::page_output_addr(virt_addr)?.is_none() {
+                return Err(MapError::NotMapped);
+            }
+        }
+        Self::check_tables(&virt_range, false)?;
+
+        let result = virt_range.step_by(Granule::SIZE).try_for_each(|virt_addr| {
+            let phys_addr = Self::page_output_addr(virt_addr)?.ok_or(MapError::NotMapped)?;
+
+            self.update_page(virt_addr, Some((phys_addr, attribute_fields)))
+        });
+
+        barrier::isb(barrier::SY);
+
+        result
+    }
+
+    /// Checks that the virtual range and physical start address are granule aligned and that the
+    /// range lies within the address space.
+    fn check_range(virt_range: &Range<usize>, phys_start: usize) -> Result<(), MapError> {
+        let granule_mask = Granule::SIZE - 1;
+
+        if (virt_range.start | virt_range.end | phys_start) & granule_mask != 0 {
+            return Err(MapError::Misaligned);
+        }
+
+        // With the `higher-half` feature, the range must lie above `KERNEL_VIRT_OFFSET`.
+        let table_start = virt_range.start.checked_sub(KERNEL_VIRT_OFFSET);
+        let table_end = virt_range.end.checked_sub(KERNEL_VIRT_OFFSET);
+
+        let (table_start, table_end) = match (table_start, table_end) {
+            (Some(start), Some(end)) => (start, end),
+            _ => return Err(MapError::OutOfRange),
+        };
+
+        if table_start > table_end
+            || table_end > unsafe { TRANSLATION_TABLE.addr_space_size() }
+            || phys_start.checked_add(virt_range.len()).is_none()
+        {
+            return Err(MapError::OutOfRange);
+        }
+
+        Ok(())
+    }
+
+    /// Checks that the pool has enough tables left for splitting the blocks of the range, and with
+    /// `allocate` for the windows without a table.
+    fn check_tables(virt_range: &Range<usize>, allocate: bool) -> Result<(), MapError> {
+        let table_range = Self::table_addr(virt_range.start)..Self::table_addr(virt_range.end);
+
+        unsafe {
+            let needed = TRANSLATION_TABLE
+                .tables_needed(table_range, allocate)
+                .map_err(map_error)?;
+
+            if needed > TRANSLATION_TABLE.free_tables() {
+                return Err(MapError::OutOfTables);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Returns the address, which the translation table translates for the virtual address. With
+    /// the `higher-half` feature, the table translates the range starting at `KERNEL_VIRT_OFFSET`.
+    fn table_addr(virt_addr: usize) -> usize {
+        virt_addr.wrapping_sub(KERNEL_VIRT_OFFSET)
+    }
+
+    /// Returns the physical output address of the page, or `None` if it is unmapped.
+    unsafe fn page_output_addr(virt_addr: usize) -> Result<Option<usize>, MapError> {
+        TRANSLATION_TABLE
+            .page_output_addr(Self::table_addr(virt_addr))
+            .map_err(map_error)
+    }
+
+    /// Replaces the descriptor of a single page following the break-before-make sequence, as
+    /// required by the ARMv8-A Architecture Reference Manual, section D5.10.1.
+    unsafe fn update_page(
+        &self,
+        virt_addr: usize,
+        new: Option<(usize, AttributeFields)>,
+    ) -> Result<(), MapError> {
+        let table_addr = Self::table_addr(virt_addr);
+
+        // A page within a block can't be changed on its own. The whole block is briefly unmapped
+        // while it is replaced by a next level table. TLB entries may cache any part of the block,
+        // which a single page invalidation doesn't cover.
+        TRANSLATION_TABLE
+            .split_block(table_addr, || {
+                barrier::dsb(barrier::ISHST);
+                invalidate_tlb_all();
+                barrier::dsb(barrier::ISH);
+            })
+            .map_err(map_error)?;
+
+        // Break: Invalidate the descriptor and any TLB entries caching it on all cores.
+        TRANSLATION_TABLE
+            .clear_page(table_addr)
+            .map_err(map_error)?;
+
+        barrier::dsb(barrier::ISHST);
+        invalidate_tlb_page(virt_addr);
+        barrier::dsb(barrier::ISH);
+
+        // Make: Write the new descriptor.
+        if let Some((phys_addr, attribute_fields)) = new {
+            TRANSLATION_TABLE
+                .set_page(table_addr, phys_addr, &attribute_fields)
+                .map_err(map_error)?;
+
+            barrier::dsb(barrier::ISHST);
+        }
+
+        Ok(())
+    }
+
+    /// Returns the physical address and the attributes, which the virtual address is translated
+    /// to, or `None` if it is unmapped.
+    ///
+    /// If the MMU of the executing core is enabled, the address is first translated by the MMU
+    /// itself. The translation tables are walked in software for the attributes, which the MMU
+    /// doesn't report, and must agree with it. A disagreement, such as a stale TLB entry, is
+    /// reported as `TranslateError::Mismatch`. If the MMU is disabled, only the software walk is
+    /// used.
+    pub fn translate(
+        &self,
+        virt_addr: usize,
+    ) -> Result<Option<(usize, AttributeFields)>, TranslateError> {
+        let hardware = if self.is_enabled() {
+            Some(translate_hw(virt_addr))
+        } else {
+            None
+        };
+
+        let table_addr = virt_addr
+            .checked_sub(KERNEL_VIRT_OFFSET)
+            .ok_or(TranslateError::OutOfRange)?;
+        let software = unsafe { TRANSLATION_TABLE.translate(table_addr) }
+            .map_err(|_| TranslateError::OutOfRange)?;
+        let software_phys_addr = software.map(|(phys_addr, _)| phys_addr);
+
+        match hardware {
+            Some(hardware) if hardware != software_phys_addr => Err(TranslateError::Mismatch {
+                hardware,
+                software: software_phys_addr,
+            }),
+            _ => Ok(software),
+        }
+    }
+
+    /// Returns a dump of the live translation tables for printing, which lists the mapped ranges.
+    /// Contiguous ranges with the same attributes are coalesced.
+    ///
+    /// Changes through `map()`, `unmap()` and `protect()` while the dump is printed may tear it.
+    pub fn dump(&self) -> TranslationTableDump {
+        TranslationTableDump
+    }
+
     #[inline(always)]
+    #[cfg(not(feature = "hypervisor"))]
     pub fn is_enabled(&self) -> bool {
         SCTLR_EL1.matches_all(SCTLR_EL1::M::Enable)
     }
+
+    #[inline(always)]
+    #[cfg(feature = "hypervisor")]
+    pub fn is_enabled(&self) -> bool {
+        let sctlr: u64;
+
+        unsafe {
+            asm!("mrs {}, SCTLR_EL2", out(reg) sctlr, options(nomem, nostack, preserves_flags));
+        }
+
+        sctlr & SCTLR_ELX_M != 0
+    }
+}
+
+/// The physical address size supported by the executing core, encoded as in
+/// ID_AA64MMFR0_EL1.PARange. TCR_EL1.IPS, TCR_EL2.PS and VTCR_EL2.PS use the same encoding.
+///
+/// Capped to 48 bits, which is the limit of the descriptors without FEAT_LPA.
+pub(crate) fn phys_addr_range() -> u64 {
+    const PA_RANGE_48_BITS: u64 = 0b0101;
+
+    (ID_AA64MMFR0_EL1.get() & 0xF).min(PA_RANGE_48_BITS)
+}
+
+/// Translates the virtual address for a read with the MMU of the executing core, using the
+/// address translation instruction of the current translation regime. Returns `None` if the
+/// translation faults.
+fn translate_hw(virt_addr: usize) -> Option<usize> {
+    // PAR_EL1 also holds the result of AT S1E2R.
+    const PAR_F: u64 = 1 << 0;
+    const PAR_PA_MASK: u64 = 0x0000_FFFF_FFFF_F000;
+
+    let par: u64;
+
+    unsafe {
+        #[cfg(not(feature = "hypervisor"))]
+        asm!(
+            "at s1e1r, {va}",
+            "isb",
+            "mrs {par}, PAR_EL1",
+            va = in(reg) virt_addr,
+            par = out(reg) par,
+            options(nostack, preserves_flags)
+        );
+        #[cfg(feature = "hypervisor")]
+        asm!(
+            "at s1e2r, {va}",
+            "isb",
+            "mrs {par}, PAR_EL1",
+            va = in(reg) virt_addr,
+            par = out(reg) par,
+            options(nostack, preserves_flags)
+        );
+    }
+
+    if par & PAR_F != 0 {
+        return None;
+    }
+
+    Some((par & PAR_PA_MASK) as usize | (virt_addr & 0xFFF))
+}
+
+/// Invalidates the TLB entries for the virtual address on all cores of the inner shareable domain.
+#[inline(always)]
+unsafe fn invalidate_tlb_page(virt_addr: usize) {
+    // The operand holds bits [55:12] of the virtual address in bits [43:0].
+    let operand = ((virt_addr >> 12) & ((1 << 44) - 1)) as u64;
+
+    #[cfg(not(feature = "hypervisor"))]
+    asm!("tlbi vae1is, {}", in(reg) operand, options(nostack, preserves_flags));
+    #[cfg(feature = "hypervisor")]
+    asm!("tlbi vae2is, {}", in(reg) operand, options(nostack, preserves_flags));
+}
+
+/// Invalidates all TLB entries of the translation regime on all cores, e.g. of a removed block
+/// descriptor.
+#[inline(always)]
+unsafe fn invalidate_tlb_all() {
+    #[cfg(not(feature = "hypervisor"))]
+    asm!("tlbi vmalle1is", options(nostack, preserves_flags));
+    #[cfg(feature = "hypervisor")]
+    asm!("tlbi alle2is", options(nostack, preserves_flags));
+}
+
+/// Converts an error of the translation table into a mapping error.
+fn map_error(e: &'static str) -> MapError {
+    if e == OUT_OF_TABLES {
+        MapError::OutOfTables
+    } else {
+        MapError::OutOfRange
+    }
+}
+
+/// Invalidates all TLB entries of the EL1 translation regime on the executing core.
+#[cfg(feature = "higher-half")]
+#[inline(always)]
+unsafe fn invalidate_tlb_local() {
+    barrier::isb(barrier::SY);
+    asm!("tlbi vmalle1", options(nostack, preserves_flags));
+    barrier::dsb(barrier::ISH);
+    barrier::isb(barrier::SY);
+}
+
+impl fmt::Display for TranslationTableDump {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        unsafe { &TRANSLATION_TABLE }.for_each_mapped_range(|range| {
+            let range = MappedRange {
+                virt_start: range.virt_start + KERNEL_VIRT_OFFSET,
+                ..*range
+            };
+
+            writeln!(f, "{}", range)
+        })
+    }
 }
 
 /// Return a reference to the MMU instance.
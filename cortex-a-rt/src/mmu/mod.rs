@@ -3,10 +3,13 @@
 // Copyright (c) 2020-2021 Andre Richter <andre.o.richter@gmail.com>
 
 pub mod layout;
+#[cfg(target_arch = "aarch64")]
 pub mod mmu;
 pub mod translation_table;
 
+#[cfg(target_arch = "aarch64")]
 pub use mmu::mmu;
+pub use translation_table::MappedRange;
 
 use core::{fmt, ops::RangeInclusive};
 
@@ -14,12 +17,62 @@ use core::{fmt, ops::RangeInclusive};
 #[derive(Debug)]
 pub enum MMUEnableError {
     AlreadyEnabled,
+    InvalidLayout(LayoutError),
     Other(&'static str),
 }
 
+/// Layout validation errors variants, which name the offending descriptors.
+#[derive(Debug)]
+pub enum LayoutError {
+    /// The ranges of the two descriptors overlap.
+    Overlap(&'static str, &'static str),
+    /// The range or the output address of the descriptor is not aligned to the translation
+    /// granule.
+    Misaligned(&'static str),
+    /// The range of the descriptor exceeds the last address of the layout.
+    OutOfRange(&'static str),
+}
+
+/// Runtime mapping errors variants.
+#[derive(Debug)]
+pub enum MapError {
+    /// Addresses or size are not aligned to the translation granule.
+    Misaligned,
+    /// The range exceeds the address space.
+    OutOfRange,
+    /// Part of the range to be protected is not mapped.
+    NotMapped,
+    /// The pool has too few translation tables left for the range.
+    OutOfTables,
+}
+
+/// Address translation query errors variants.
+#[derive(Debug)]
+pub enum TranslateError {
+    /// The address is not translated by the runtime's translation tables.
+    OutOfRange,
+    /// The MMU of the executing core translates the address differently than the translation
+    /// tables, for example because of a stale TLB entry. Holds the physical addresses reported by
+    /// both, `None` if unmapped.
+    Mismatch {
+        hardware: Option<usize>,
+        software: Option<usize>,
+    },
+}
+
 /// Describes the characteristics of a translation granule.
 pub struct TranslationGranule<const GRANULE_SIZE: usize>;
 
+pub type Granule512MiB = TranslationGranule<{ 512 * 1024 * 1024 }>;
+pub type Granule64KiB = TranslationGranule<{ 64 * 1024 }>;
+pub type Granule4KiB = TranslationGranule<{ 4 * 1024 }>;
+
+/// The translation granule, which is also the page size.
+#[cfg(not(feature = "granule-4k"))]
+pub type Granule = Granule64KiB;
+#[cfg(feature = "granule-4k")]
+pub type Granule = Granule4KiB;
+
 /// Describes properties of an address space.
 pub struct AddressSpace<const AS_SIZE: usize>;
 
@@ -28,28 +81,43 @@ pub struct AddressSpace<const AS_SIZE: usize>;
 pub enum Translation {
     Identity,
     Offset(usize),
+    /// The range is left unmapped and any access to it faults.
+    Unmapped,
 }
 
 /// Architecture agnostic memory attributes.
-#[derive(Copy, Clone)]
+#[derive(Copy, Clone, PartialEq, Eq)]
 pub enum MemAttributes {
     CacheableDRAM,
+    /// Normal memory, which is not cached, such as DMA descriptor rings and framebuffers.
+    NonCacheable,
+    /// Normal memory, which is cached for reads and written through to memory.
+    WriteThrough,
+    /// Device memory, which allows early write acknowledgement (nGnRE).
     Device,
+    /// Device memory without early write acknowledgement (nGnRnE), so that a write completes only
+    /// once it has reached the device.
+    DeviceNGnRnE,
 }
 
 /// Architecture agnostic access permissions.
-#[derive(Copy, Clone)]
+#[derive(Copy, Clone, PartialEq, Eq)]
 pub enum AccessPermissions {
     ReadOnly,
     ReadWrite,
 }
 
 /// Collection of memory attributes.
-#[derive(Copy, Clone)]
+#[derive(Copy, Clone, PartialEq, Eq)]
 pub struct AttributeFields {
     pub mem_attributes: MemAttributes,
     pub acc_perms: AccessPermissions,
+    /// Privileged (EL1) execute-never.
     pub execute_never: bool,
+    /// Grants EL0 the same access permissions as EL1.
+    pub el0_access: bool,
+    /// Unprivileged (EL0) execute-never.
+    pub el0_execute_never: bool,
 }
 
 /// Architecture agnostic descriptor for a memory range.
@@ -64,11 +132,60 @@ impl fmt::Display for MMUEnableError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
             MMUEnableError::AlreadyEnabled => write!(f, "MMU is already enabled"),
+            MMUEnableError::InvalidLayout(x) => write!(f, "Invalid memory layout: {}", x),
             MMUEnableError::Other(x) => write!(f, "{}", x),
         }
     }
 }
 
+impl fmt::Display for LayoutError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LayoutError::Overlap(a, b) => write!(f, "Descriptors \"{}\" and \"{}\" overlap", a, b),
+            LayoutError::Misaligned(x) => write!(
+                f,
+                "Descriptor \"{}\" is not aligned to the translation granule",
+                x
+            ),
+            LayoutError::OutOfRange(x) => write!(
+                f,
+                "Descriptor \"{}\" exceeds the address space of the layout",
+                x
+            ),
+        }
+    }
+}
+
+impl fmt::Display for MapError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MapError::Misaligned => write!(f, "Range is not aligned to the translation granule"),
+            MapError::OutOfRange => write!(f, "Range exceeds the address space"),
+            MapError::NotMapped => write!(f, "Range is not mapped"),
+            MapError::OutOfTables => write!(f, "Not enough translation tables"),
+        }
+    }
+}
+
+impl fmt::Display for TranslateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let write_addr = |f: &mut fmt::Formatter<'_>, addr: Option<usize>| match addr {
+            Some(addr) => write!(f, "{:#x}", addr),
+            None => write!(f, "unmapped"),
+        };
+
+        match self {
+            TranslateError::OutOfRange => write!(f, "Address exceeds the address space"),
+            TranslateError::Mismatch { hardware, software } => {
+                write!(f, "MMU translates to ")?;
+                write_addr(f, *hardware)?;
+                write!(f, ", but the translation tables to ")?;
+                write_addr(f, *software)
+            }
+        }
+    }
+}
+
 impl<const GRANULE_SIZE: usize> TranslationGranule<GRANULE_SIZE> {
     /// The granule's size.
     pub const SIZE: usize = Self::size_checked();
@@ -106,6 +223,8 @@ impl Default for AttributeFields {
             mem_attributes: MemAttributes::CacheableDRAM,
             acc_perms: AccessPermissions::ReadWrite,
             execute_never: true,
+            el0_access: false,
+            el0_execute_never: true,
         }
     }
 }
@@ -117,42 +236,81 @@ impl fmt::Display for TranslationDescriptor {
         // Rust to copy the value.
         let start = *(self.virtual_range)().start();
         let end = *(self.virtual_range)().end();
-        let size = end - start + 1;
+        // Empty ranges are represented with end == start - 1.
+        let size = end.wrapping_sub(start).wrapping_add(1);
 
-        // log2(1024).
-        const KIB_RSHIFT: u32 = 10;
+        let (size, unit) = size_with_unit(size);
 
-        // log2(1024 * 1024).
-        const MIB_RSHIFT: u32 = 20;
+        if let Translation::Unmapped = self.physical_range_translation {
+            return write!(
+                f,
+                "      {:#010x} - {:#010x} | {: >3} {} | {: <18} | {}",
+                start, end, size, unit, "Unmapped", self.name
+            );
+        }
 
-        let (size, unit) = if (size >> MIB_RSHIFT) > 0 {
-            (size >> MIB_RSHIFT, "MiB")
-        } else if (size >> KIB_RSHIFT) > 0 {
-            (size >> KIB_RSHIFT, "KiB")
-        } else {
-            (size, "Byte")
-        };
+        write!(
+            f,
+            "      {:#010x} - {:#010x} | {: >3} {} | {} | {}",
+            start, end, size, unit, self.attribute_fields, self.name
+        )
+    }
+}
+
+/// Human-readable output of a MappedRange, in the format of a TranslationDescriptor with the
+/// physical start address in place of the name.
+impl fmt::Display for MappedRange {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let end = self.virt_start + (self.size - 1);
+        let (size, unit) = size_with_unit(self.size);
+
+        write!(
+            f,
+            "      {:#010x} - {:#010x} | {: >3} {} | {} | {:#010x}",
+            self.virt_start, end, size, unit, self.attribute_fields, self.phys_start
+        )
+    }
+}
 
-        let attr = match self.attribute_fields.mem_attributes {
+/// Human-readable output of AttributeFields, 18 characters wide.
+impl fmt::Display for AttributeFields {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let attr = match self.mem_attributes {
             MemAttributes::CacheableDRAM => "C",
+            MemAttributes::NonCacheable => "NC",
+            MemAttributes::WriteThrough => "WT",
             MemAttributes::Device => "Dev",
+            MemAttributes::DeviceNGnRnE => "nE",
         };
 
-        let acc_p = match self.attribute_fields.acc_perms {
+        let acc_p = match self.acc_perms {
             AccessPermissions::ReadOnly => "RO",
             AccessPermissions::ReadWrite => "RW",
         };
 
-        let xn = if self.attribute_fields.execute_never {
-            "PXN"
-        } else {
-            "PX"
-        };
+        let xn = if self.execute_never { "PXN" } else { "PX" };
 
-        write!(
-            f,
-            "      {:#010x} - {:#010x} | {: >3} {} | {: <3} {} {: <3} | {}",
-            start, end, size, unit, attr, acc_p, xn, self.name
-        )
+        let el0 = if self.el0_access { "EL0" } else { "EL1" };
+
+        let uxn = if self.el0_execute_never { "UXN" } else { "UX" };
+
+        write!(f, "{: <3} {} {} {: <3} {: <3}", attr, acc_p, el0, xn, uxn)
+    }
+}
+
+/// Returns the size in the largest unit, of which it spans at least one.
+fn size_with_unit(size: usize) -> (usize, &'static str) {
+    // log2(1024).
+    const KIB_RSHIFT: u32 = 10;
+
+    // log2(1024 * 1024).
+    const MIB_RSHIFT: u32 = 20;
+
+    if (size >> MIB_RSHIFT) > 0 {
+        (size >> MIB_RSHIFT, "MiB")
+    } else if (size >> KIB_RSHIFT) > 0 {
+        (size >> KIB_RSHIFT, "KiB")
+    } else {
+        (size, "Byte")
     }
 }
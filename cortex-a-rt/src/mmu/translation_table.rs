@@ -4,7 +4,9 @@
 
 //! Architectural translation table.
 //!
-//! Only 64 KiB granule is supported.
+//! The 64 KiB granule walks levels 2 and 3, with 512 MiB blocks at level 2. The 4 KiB granule,
+//! selected with the `granule-4k` feature, walks levels 0 to 3, with 1 GiB blocks at level 1 and
+//! 2 MiB blocks at level 2.
 //!
 //! # Orientation
 //!
@@ -13,26 +15,109 @@
 //!
 //! crate::memory::mmu::translation_table::arch_translation_table
 
-use core::convert;
+use core::{convert, ops::Range};
 use register::{register_bitfields, InMemoryRegister};
 
 use super::{
-    layout::VirtualMemoryLayout,
-    mmu::{Granule512MiB, Granule64KiB},
-    AccessPermissions, AttributeFields, MemAttributes,
+    layout::VirtualMemoryLayout, AccessPermissions, AddressSpace, AttributeFields, Granule,
+    Granule512MiB, MemAttributes,
 };
 
 /// Constants for indexing the MAIR_EL1.
 pub mod mair {
     pub const DEVICE: u64 = 0;
     pub const NORMAL: u64 = 1;
+    pub const NORMAL_NON_CACHEABLE: u64 = 2;
+    pub const NORMAL_WRITE_THROUGH: u64 = 3;
+    pub const DEVICE_NGNRNE: u64 = 4;
+}
+
+/// Number of descriptors in a table, which fills exactly one granule.
+const ENTRIES: usize = Granule::SIZE / 8;
+
+/// Virtual address bits resolved by each level.
+const BITS_PER_LEVEL: usize = Granule::SHIFT - 3;
+
+/// Level of the root table.
+#[cfg(not(feature = "granule-4k"))]
+const START_LEVEL: usize = 2;
+#[cfg(feature = "granule-4k")]
+const START_LEVEL: usize = 0;
+
+/// Highest level, whose descriptors can be blocks.
+#[cfg(not(feature = "granule-4k"))]
+const FIRST_BLOCK_LEVEL: usize = 2;
+#[cfg(feature = "granule-4k")]
+const FIRST_BLOCK_LEVEL: usize = 1;
+
+/// Level of the page descriptors.
+const PAGE_LEVEL: usize = 3;
+
+/// Shift of the address fields of descriptors. Addresses are stored in units of 4 KiB independent
+/// of the granule, larger granules only require more of the low bits to be zero.
+const DESCRIPTOR_ADDR_SHIFT: usize = 12;
+
+/// log2 of the largest address space, which is translated by the root table.
+const MAX_ADDR_SPACE_SHIFT: usize = level_shift(START_LEVEL) + BITS_PER_LEVEL;
+
+/// log2 of the smallest address space, whose walk starts at the root table level.
+const MIN_ADDR_SPACE_SHIFT: usize = level_shift(START_LEVEL) + 1;
+
+/// Start of the higher half, at which the runtime is linked with the `higher-half` feature.
+/// TTBR1_EL1 translates the range of the root table at the top of the address space with the same
+/// tables as TTBR0_EL1, so address `x` of the tables is translated at `KERNEL_VIRT_OFFSET + x`.
+/// Zero without the feature. Must match `__kernel_virt_offset` of build.rs.
+#[cfg(feature = "higher-half")]
+pub const KERNEL_VIRT_OFFSET: usize = !((1 << MAX_ADDR_SPACE_SHIFT) - 1);
+#[cfg(not(feature = "higher-half"))]
+pub const KERNEL_VIRT_OFFSET: usize = 0;
+
+/// Error of all operations, which ran out of tables in the pool.
+pub const OUT_OF_TABLES: &str = "Not enough translation tables";
+
+impl<const AS_SIZE: usize> AddressSpace<AS_SIZE> {
+    /// Checks for architectural restrictions.
+    pub const fn arch_address_space_size_sanity_checks() {
+        // Size must be at least one full 512 MiB table.
+        assert!((AS_SIZE % Granule512MiB::SIZE) == 0);
+
+        // Check for 48 bit virtual address size as maximum, which is supported by any ARMv8
+        // version.
+        assert!(AS_SIZE <= (1 << 48));
+    }
+}
+
+/// Returns log2 of the size of the range translated by a descriptor of the level.
+const fn level_shift(level: usize) -> usize {
+    Granule::SHIFT + (PAGE_LEVEL - level) * BITS_PER_LEVEL
+}
+
+/// Returns the number of tables, which are needed below a block or invalid descriptor of the level,
+/// so that every page of the range within its window is translated by a page descriptor.
+fn tables_below(level: usize, range: Range<usize>) -> usize {
+    if level == PAGE_LEVEL {
+        return 0;
+    }
+
+    let shift = level_shift(level + 1);
+    let next_level_tables: usize = ((range.start >> shift)..=((range.end - 1) >> shift))
+        .map(|window| {
+            let window_start = window << shift;
+            let start = range.start.max(window_start);
+            let end = range.end.min(window_start + (1 << shift));
+
+            tables_below(level + 1, start..end)
+        })
+        .sum();
+
+    1 + next_level_tables
 }
 
 // A table descriptor, as per ARMv8-A Architecture Reference Manual Figure D5-15.
 register_bitfields! {u64,
     STAGE1_TABLE_DESCRIPTOR [
-        /// Physical address of the next descriptor.
-        NEXT_LEVEL_TABLE_ADDR_64KiB OFFSET(16) NUMBITS(32) [], // [47:16]
+        /// Physical address of the next table, aligned to the granule.
+        NEXT_LEVEL_TABLE_ADDR OFFSET(12) NUMBITS(36) [], // [47:12]
 
         TYPE  OFFSET(1) NUMBITS(1) [
             Block = 0,
@@ -46,7 +131,8 @@ register_bitfields! {u64,
     ]
 }
 
-// A level 3 page descriptor, as per ARMv8-A Architecture Reference Manual Figure D5-17.
+// A block or page descriptor, as per ARMv8-A Architecture Reference Manual Figure D5-17. The TYPE
+// bit distinguishes pages at level 3 from blocks at the levels above.
 register_bitfields! {u64,
     STAGE1_PAGE_DESCRIPTOR [
         /// Unprivileged execute-never.
@@ -61,8 +147,8 @@ register_bitfields! {u64,
             True = 1
         ],
 
-        /// Physical address of the next table descriptor (lvl2) or the page descriptor (lvl3).
-        OUTPUT_ADDR_64KiB OFFSET(16) NUMBITS(32) [], // [47:16]
+        /// Physical address of the block or page, aligned to its size.
+        OUTPUT_ADDR OFFSET(12) NUMBITS(36) [], // [47:12]
 
         /// Access flag.
         AF       OFFSET(10) NUMBITS(1) [
@@ -88,7 +174,7 @@ register_bitfields! {u64,
         AttrIndx OFFSET(2) NUMBITS(3) [],
 
         TYPE     OFFSET(1) NUMBITS(1) [
-            Reserved_Invalid = 0,
+            Block = 0,
             Page = 1
         ],
 
@@ -99,72 +185,119 @@ register_bitfields! {u64,
     ]
 }
 
-/// A table descriptor for 64 KiB aperture.
-///
-/// The output points to the next table.
-#[derive(Copy, Clone)]
-#[repr(C)]
-struct TableDescriptor {
-    value: u64,
+// A stage 2 block or page descriptor, as per ARMv8-A Architecture Reference Manual Figure D5-17.
+register_bitfields! {u64,
+    STAGE2_PAGE_DESCRIPTOR [
+        /// Execute-never.
+        XN       OFFSET(54) NUMBITS(1) [
+            False = 0,
+            True = 1
+        ],
+
+        /// Physical address of the block or page, aligned to its size.
+        OUTPUT_ADDR OFFSET(12) NUMBITS(36) [], // [47:12]
+
+        /// Access flag.
+        AF       OFFSET(10) NUMBITS(1) [
+            False = 0,
+            True = 1
+        ],
+
+        /// Shareability field.
+        SH       OFFSET(8) NUMBITS(2) [
+            OuterShareable = 0b10,
+            InnerShareable = 0b11
+        ],
+
+        /// Stage 2 access permissions.
+        S2AP     OFFSET(6) NUMBITS(2) [
+            None = 0b00,
+            ReadOnly = 0b01,
+            WriteOnly = 0b10,
+            ReadWrite = 0b11
+        ],
+
+        /// Stage 2 memory attributes, which are encoded directly instead of indexing MAIR.
+        MemAttr  OFFSET(2) NUMBITS(4) [
+            Device_nGnRnE = 0b0000,
+            Device_nGnRE = 0b0001,
+            Normal_NonCacheable = 0b0101,
+            Normal_WriteThrough = 0b1010,
+            Normal_WriteBack = 0b1111
+        ],
+
+        TYPE     OFFSET(1) NUMBITS(1) [
+            Block = 0,
+            Page = 1
+        ],
+
+        VALID    OFFSET(0) NUMBITS(1) [
+            False = 0,
+            True = 1
+        ]
+    ]
 }
 
-/// A page descriptor with 64 KiB aperture.
+/// A descriptor of any level.
 ///
-/// The output points to physical memory.
+/// A table descriptor points to the next table. A block or page descriptor points to physical
+/// memory, blocks and pages share the attributes.
 #[derive(Copy, Clone)]
 #[repr(C)]
-struct PageDescriptor {
+struct Descriptor {
     value: u64,
 }
 
 trait StartAddr {
-    fn phys_start_addr_u64(&self) -> u64;
-    fn phys_start_addr_usize(&self) -> usize;
+    fn phys_start_addr_u64(&self, virt_offset: usize) -> u64;
+    fn phys_start_addr_usize(&self, virt_offset: usize) -> usize;
+}
+
+/// A virtual range, which is translated to a contiguous physical range with the same attributes.
+#[derive(Copy, Clone)]
+pub struct MappedRange {
+    pub virt_start: usize,
+    pub phys_start: usize,
+    pub size: usize,
+    pub attribute_fields: AttributeFields,
 }
 
-/// Big monolithic struct for storing the translation tables. Individual levels must be 64 KiB
-/// aligned, so the lvl3 is put first.
+/// Big monolithic struct for storing the translation tables. Tables must be aligned to the
+/// granule, which is at most 64 KiB.
+///
+/// The tables form a pool. The first one is the root table, the others are handed out to windows
+/// of the next levels, which contain mapped pages and can't be translated by a block. Unmapped
+/// holes of a sparse address space therefore take up no memory.
+///
+/// The descriptors and TTBR hold physical table addresses, which are `VIRT_OFFSET` below the
+/// virtual addresses the tables are accessed at. The runtime's tables use `KERNEL_VIRT_OFFSET`.
 #[repr(C)]
 #[repr(align(65536))]
-pub struct FixedSizeTranslationTable<const NUM_TABLES: usize> {
-    /// Page descriptors, covering 64 KiB windows per entry.
-    lvl3: [[PageDescriptor; 8192]; NUM_TABLES],
+pub struct FixedSizeTranslationTable<const NUM_TABLES: usize, const VIRT_OFFSET: usize> {
+    /// Pool of tables, the root table first.
+    tables: [[Descriptor; ENTRIES]; NUM_TABLES],
 
-    /// Table descriptors, covering 512 MiB windows.
-    lvl2: [TableDescriptor; NUM_TABLES],
-}
+    /// Number of tables handed out from the pool. Unused tables only contain invalid descriptors.
+    num_tables_used: usize,
 
-// The binary is still identity mapped, so we don't need to convert here.
-impl<T, const N: usize> StartAddr for [T; N] {
-    fn phys_start_addr_u64(&self) -> u64 {
-        self as *const T as u64
-    }
+    /// Size of the populated address space, a power of two.
+    addr_space_size: usize,
+}
 
-    fn phys_start_addr_usize(&self) -> usize {
-        self as *const _ as usize
-    }
+/// Parameters of populating the translation table from a layout.
+struct Populate<'a, L: VirtualMemoryLayout> {
+    layout: &'a L,
+    max_virt_addr: usize,
+    page_descriptor: fn(usize, &AttributeFields) -> Descriptor,
 }
 
-impl TableDescriptor {
-    /// Create an instance.
-    ///
-    /// Descriptor is invalid by default.
-    pub const fn new_zeroed() -> Self {
-        Self { value: 0 }
+impl<T, const N: usize> StartAddr for [T; N] {
+    fn phys_start_addr_u64(&self, virt_offset: usize) -> u64 {
+        self.phys_start_addr_usize(virt_offset) as u64
     }
 
-    /// Create an instance pointing to the supplied address.
-    pub fn from_next_lvl_table_addr(phys_next_lvl_table_addr: usize) -> Self {
-        let val = InMemoryRegister::<u64, STAGE1_TABLE_DESCRIPTOR::Register>::new(0);
-
-        let shifted = phys_next_lvl_table_addr >> Granule64KiB::SHIFT;
-        val.write(
-            STAGE1_TABLE_DESCRIPTOR::NEXT_LEVEL_TABLE_ADDR_64KiB.val(shifted as u64)
-                + STAGE1_TABLE_DESCRIPTOR::TYPE::Table
-                + STAGE1_TABLE_DESCRIPTOR::VALID::True,
-        );
-
-        TableDescriptor { value: val.get() }
+    fn phys_start_addr_usize(&self, virt_offset: usize) -> usize {
+        self as *const _ as usize - virt_offset
     }
 }
 
@@ -179,33 +312,116 @@ impl convert::From<AttributeFields>
                 STAGE1_PAGE_DESCRIPTOR::SH::InnerShareable
                     + STAGE1_PAGE_DESCRIPTOR::AttrIndx.val(mair::NORMAL)
             }
+            MemAttributes::NonCacheable => {
+                STAGE1_PAGE_DESCRIPTOR::SH::InnerShareable
+                    + STAGE1_PAGE_DESCRIPTOR::AttrIndx.val(mair::NORMAL_NON_CACHEABLE)
+            }
+            MemAttributes::WriteThrough => {
+                STAGE1_PAGE_DESCRIPTOR::SH::InnerShareable
+                    + STAGE1_PAGE_DESCRIPTOR::AttrIndx.val(mair::NORMAL_WRITE_THROUGH)
+            }
             MemAttributes::Device => {
                 STAGE1_PAGE_DESCRIPTOR::SH::OuterShareable
                     + STAGE1_PAGE_DESCRIPTOR::AttrIndx.val(mair::DEVICE)
             }
+            MemAttributes::DeviceNGnRnE => {
+                STAGE1_PAGE_DESCRIPTOR::SH::OuterShareable
+                    + STAGE1_PAGE_DESCRIPTOR::AttrIndx.val(mair::DEVICE_NGNRNE)
+            }
         };
 
+        // In the EL2 translation regime, there is no EL0 and AP[1] is RES1.
+        #[cfg(feature = "hypervisor")]
+        let el0_access = true;
+        #[cfg(not(feature = "hypervisor"))]
+        let el0_access = attribute_fields.el0_access;
+
         // Access Permissions.
-        desc += match attribute_fields.acc_perms {
-            AccessPermissions::ReadOnly => STAGE1_PAGE_DESCRIPTOR::AP::RO_EL1,
-            AccessPermissions::ReadWrite => STAGE1_PAGE_DESCRIPTOR::AP::RW_EL1,
+        desc += match (attribute_fields.acc_perms, el0_access) {
+            (AccessPermissions::ReadOnly, false) => STAGE1_PAGE_DESCRIPTOR::AP::RO_EL1,
+            (AccessPermissions::ReadWrite, false) => STAGE1_PAGE_DESCRIPTOR::AP::RW_EL1,
+            (AccessPermissions::ReadOnly, true) => STAGE1_PAGE_DESCRIPTOR::AP::RO_EL1_EL0,
+            (AccessPermissions::ReadWrite, true) => STAGE1_PAGE_DESCRIPTOR::AP::RW_EL1_EL0,
         };
 
         // The execute-never attribute is mapped to PXN in AArch64.
+        #[cfg(not(feature = "hypervisor"))]
+        {
+            desc += if attribute_fields.execute_never {
+                STAGE1_PAGE_DESCRIPTOR::PXN::True
+            } else {
+                STAGE1_PAGE_DESCRIPTOR::PXN::False
+            };
+
+            desc += if attribute_fields.el0_execute_never {
+                STAGE1_PAGE_DESCRIPTOR::UXN::True
+            } else {
+                STAGE1_PAGE_DESCRIPTOR::UXN::False
+            };
+        }
+
+        // The EL2 translation regime only has a single XN bit at the position of UXN, PXN is RES0.
+        #[cfg(feature = "hypervisor")]
+        {
+            desc += if attribute_fields.execute_never {
+                STAGE1_PAGE_DESCRIPTOR::UXN::True
+            } else {
+                STAGE1_PAGE_DESCRIPTOR::UXN::False
+            };
+
+            desc += STAGE1_PAGE_DESCRIPTOR::PXN::False;
+        }
+
+        desc
+    }
+}
+
+/// Convert the kernel's generic memory attributes to HW-specific attributes of stage 2 translation.
+impl convert::From<AttributeFields>
+    for register::FieldValue<u64, STAGE2_PAGE_DESCRIPTOR::Register>
+{
+    fn from(attribute_fields: AttributeFields) -> Self {
+        // Memory attributes.
+        let mut desc = match attribute_fields.mem_attributes {
+            MemAttributes::CacheableDRAM => {
+                STAGE2_PAGE_DESCRIPTOR::SH::InnerShareable
+                    + STAGE2_PAGE_DESCRIPTOR::MemAttr::Normal_WriteBack
+            }
+            MemAttributes::NonCacheable => {
+                STAGE2_PAGE_DESCRIPTOR::SH::InnerShareable
+                    + STAGE2_PAGE_DESCRIPTOR::MemAttr::Normal_NonCacheable
+            }
+            MemAttributes::WriteThrough => {
+                STAGE2_PAGE_DESCRIPTOR::SH::InnerShareable
+                    + STAGE2_PAGE_DESCRIPTOR::MemAttr::Normal_WriteThrough
+            }
+            MemAttributes::Device => {
+                STAGE2_PAGE_DESCRIPTOR::SH::OuterShareable
+                    + STAGE2_PAGE_DESCRIPTOR::MemAttr::Device_nGnRE
+            }
+            MemAttributes::DeviceNGnRnE => {
+                STAGE2_PAGE_DESCRIPTOR::SH::OuterShareable
+                    + STAGE2_PAGE_DESCRIPTOR::MemAttr::Device_nGnRnE
+            }
+        };
+
+        // Access Permissions. EL0 and EL1 of the guest are not distinguished by stage 2.
+        desc += match attribute_fields.acc_perms {
+            AccessPermissions::ReadOnly => STAGE2_PAGE_DESCRIPTOR::S2AP::ReadOnly,
+            AccessPermissions::ReadWrite => STAGE2_PAGE_DESCRIPTOR::S2AP::ReadWrite,
+        };
+
         desc += if attribute_fields.execute_never {
-            STAGE1_PAGE_DESCRIPTOR::PXN::True
+            STAGE2_PAGE_DESCRIPTOR::XN::True
         } else {
-            STAGE1_PAGE_DESCRIPTOR::PXN::False
+            STAGE2_PAGE_DESCRIPTOR::XN::False
         };
 
-        // Always set unprivileged exectue-never as long as userspace is not implemented yet.
-        desc += STAGE1_PAGE_DESCRIPTOR::UXN::True;
-
         desc
     }
 }
 
-impl PageDescriptor {
+impl Descriptor {
     /// Create an instance.
     ///
     /// Descriptor is invalid by default.
@@ -213,13 +429,27 @@ impl PageDescriptor {
         Self { value: 0 }
     }
 
-    /// Create an instance.
+    /// Create a table descriptor pointing to the supplied address.
+    pub fn from_next_lvl_table_addr(phys_next_lvl_table_addr: usize) -> Self {
+        let val = InMemoryRegister::<u64, STAGE1_TABLE_DESCRIPTOR::Register>::new(0);
+
+        let shifted = phys_next_lvl_table_addr >> DESCRIPTOR_ADDR_SHIFT;
+        val.write(
+            STAGE1_TABLE_DESCRIPTOR::NEXT_LEVEL_TABLE_ADDR.val(shifted as u64)
+                + STAGE1_TABLE_DESCRIPTOR::TYPE::Table
+                + STAGE1_TABLE_DESCRIPTOR::VALID::True,
+        );
+
+        Self { value: val.get() }
+    }
+
+    /// Create a page descriptor, which `at_level()` turns into a block descriptor.
     pub fn from_output_addr(phys_output_addr: usize, attribute_fields: &AttributeFields) -> Self {
         let val = InMemoryRegister::<u64, STAGE1_PAGE_DESCRIPTOR::Register>::new(0);
 
-        let shifted = phys_output_addr as u64 >> Granule64KiB::SHIFT;
+        let shifted = phys_output_addr as u64 >> DESCRIPTOR_ADDR_SHIFT;
         val.write(
-            STAGE1_PAGE_DESCRIPTOR::OUTPUT_ADDR_64KiB.val(shifted)
+            STAGE1_PAGE_DESCRIPTOR::OUTPUT_ADDR.val(shifted)
                 + STAGE1_PAGE_DESCRIPTOR::AF::True
                 + STAGE1_PAGE_DESCRIPTOR::TYPE::Page
                 + STAGE1_PAGE_DESCRIPTOR::VALID::True
@@ -228,48 +458,1029 @@ impl PageDescriptor {
 
         Self { value: val.get() }
     }
+
+    /// Create a stage 2 page descriptor, which `at_level()` turns into a block descriptor.
+    pub fn from_output_addr_stage2(
+        phys_output_addr: usize,
+        attribute_fields: &AttributeFields,
+    ) -> Self {
+        let val = InMemoryRegister::<u64, STAGE2_PAGE_DESCRIPTOR::Register>::new(0);
+
+        let shifted = phys_output_addr as u64 >> DESCRIPTOR_ADDR_SHIFT;
+        val.write(
+            STAGE2_PAGE_DESCRIPTOR::OUTPUT_ADDR.val(shifted)
+                + STAGE2_PAGE_DESCRIPTOR::AF::True
+                + STAGE2_PAGE_DESCRIPTOR::TYPE::Page
+                + STAGE2_PAGE_DESCRIPTOR::VALID::True
+                + (*attribute_fields).into(),
+        );
+
+        Self { value: val.get() }
+    }
+
+    /// Returns the block or page descriptor with the same output address and attributes for the
+    /// level. Stage 1 and stage 2 descriptors share the position of the TYPE bit.
+    fn at_level(&self, level: usize) -> Self {
+        let val = InMemoryRegister::<u64, STAGE1_PAGE_DESCRIPTOR::Register>::new(self.value);
+
+        if level == PAGE_LEVEL {
+            val.modify(STAGE1_PAGE_DESCRIPTOR::TYPE::Page);
+        } else {
+            val.modify(STAGE1_PAGE_DESCRIPTOR::TYPE::Block);
+        }
+
+        Self { value: val.get() }
+    }
+
+    /// Returns the block or page descriptor `n` descriptors of the level after this one, with the
+    /// same attributes. Stage 1 and stage 2 descriptors share the position of the output address.
+    fn nth_next(&self, n: usize, level: usize) -> Self {
+        Self {
+            value: self.value + ((n as u64) << level_shift(level)),
+        }
+    }
+
+    /// Returns whether the descriptor is valid.
+    fn is_valid(&self) -> bool {
+        InMemoryRegister::<u64, STAGE1_PAGE_DESCRIPTOR::Register>::new(self.value)
+            .is_set(STAGE1_PAGE_DESCRIPTOR::VALID)
+    }
+
+    /// Returns the address of the next level table if the descriptor of the level is a valid
+    /// table descriptor.
+    fn next_lvl_table_addr(&self, level: usize) -> Option<usize> {
+        let val = InMemoryRegister::<u64, STAGE1_TABLE_DESCRIPTOR::Register>::new(self.value);
+
+        if level == PAGE_LEVEL
+            || !val.matches_all(
+                STAGE1_TABLE_DESCRIPTOR::VALID::True + STAGE1_TABLE_DESCRIPTOR::TYPE::Table,
+            )
+        {
+            return None;
+        }
+
+        let shifted = val.read(STAGE1_TABLE_DESCRIPTOR::NEXT_LEVEL_TABLE_ADDR) as usize;
+        Some(shifted << DESCRIPTOR_ADDR_SHIFT)
+    }
+
+    /// Returns the attributes of a stage 1 block or page descriptor, the inverse of the
+    /// conversion from `AttributeFields`.
+    fn attribute_fields(&self) -> AttributeFields {
+        let val = InMemoryRegister::<u64, STAGE1_PAGE_DESCRIPTOR::Register>::new(self.value);
+
+        let mem_attributes = match val.read(STAGE1_PAGE_DESCRIPTOR::AttrIndx) {
+            mair::NORMAL => MemAttributes::CacheableDRAM,
+            mair::NORMAL_NON_CACHEABLE => MemAttributes::NonCacheable,
+            mair::NORMAL_WRITE_THROUGH => MemAttributes::WriteThrough,
+            mair::DEVICE_NGNRNE => MemAttributes::DeviceNGnRnE,
+            _ => MemAttributes::Device,
+        };
+
+        // AP[2] selects read-only, AP[1] grants EL0 access.
+        let ap = val.read(STAGE1_PAGE_DESCRIPTOR::AP);
+        let acc_perms = if ap & 0b10 != 0 {
+            AccessPermissions::ReadOnly
+        } else {
+            AccessPermissions::ReadWrite
+        };
+
+        // In the EL2 translation regime, there is no EL0 and the single XN bit is at the position
+        // of UXN.
+        #[cfg(not(feature = "hypervisor"))]
+        let (el0_access, execute_never, el0_execute_never) = (
+            ap & 0b01 != 0,
+            val.is_set(STAGE1_PAGE_DESCRIPTOR::PXN),
+            val.is_set(STAGE1_PAGE_DESCRIPTOR::UXN),
+        );
+        #[cfg(feature = "hypervisor")]
+        let (el0_access, execute_never, el0_execute_never) =
+            (false, val.is_set(STAGE1_PAGE_DESCRIPTOR::UXN), true);
+
+        AttributeFields {
+            mem_attributes,
+            acc_perms,
+            execute_never,
+            el0_access,
+            el0_execute_never,
+        }
+    }
+
+    /// Returns the output address if the descriptor is a valid block or page descriptor.
+    fn output_addr(&self) -> Option<usize> {
+        let val = InMemoryRegister::<u64, STAGE1_PAGE_DESCRIPTOR::Register>::new(self.value);
+
+        if !val.is_set(STAGE1_PAGE_DESCRIPTOR::VALID) {
+            return None;
+        }
+
+        let shifted = val.read(STAGE1_PAGE_DESCRIPTOR::OUTPUT_ADDR) as usize;
+        Some(shifted << DESCRIPTOR_ADDR_SHIFT)
+    }
 }
 
 //--------------------------------------------------------------------------------------------------
 // Public Code
 //--------------------------------------------------------------------------------------------------
 
-impl<const NUM_TABLES: usize> FixedSizeTranslationTable<NUM_TABLES> {
+impl<const NUM_TABLES: usize, const VIRT_OFFSET: usize>
+    FixedSizeTranslationTable<NUM_TABLES, VIRT_OFFSET>
+{
     /// Create an instance.
     pub const fn new() -> Self {
-        // Can't have a zero-sized address space.
+        // Need at least the root table.
         assert!(NUM_TABLES > 0);
 
         Self {
-            lvl3: [[PageDescriptor::new_zeroed(); 8192]; NUM_TABLES],
-            lvl2: [TableDescriptor::new_zeroed(); NUM_TABLES],
+            tables: [[Descriptor::new_zeroed(); ENTRIES]; NUM_TABLES],
+            num_tables_used: 0,
+            addr_space_size: 0,
         }
     }
 
-    /// Iterates over all static translation table entries and fills them at once.
+    /// Iterates over the address space of the layout and fills all translation table entries at
+    /// once. The address space is rounded up to a power of two, which is large enough for the walk
+    /// to start at the root table level.
     pub fn populate_tt_entries(
         &mut self,
         layout: &impl VirtualMemoryLayout,
     ) -> Result<(), &'static str> {
-        for (l2_nr, l2_entry) in self.lvl2.iter_mut().enumerate() {
-            *l2_entry =
-                TableDescriptor::from_next_lvl_table_addr(self.lvl3[l2_nr].phys_start_addr_usize());
+        self.populate_with(layout, Descriptor::from_output_addr)
+    }
 
-            for (l3_nr, l3_entry) in self.lvl3[l2_nr].iter_mut().enumerate() {
-                let virt_addr = (l2_nr << Granule512MiB::SHIFT) + (l3_nr << Granule64KiB::SHIFT);
+    /// Same as `populate_tt_entries()`, but for stage 2 translation of guest intermediate physical
+    /// addresses. The layout maps intermediate physical to physical addresses.
+    pub fn populate_stage2_tt_entries(
+        &mut self,
+        layout: &impl VirtualMemoryLayout,
+    ) -> Result<(), &'static str> {
+        self.populate_with(layout, Descriptor::from_output_addr_stage2)
+    }
 
-                let (phys_output_addr, attribute_fields) =
-                    layout.virt_addr_properties(virt_addr)?;
+    /// Fills all entries, creating valid block and page descriptors with the provided constructor.
+    fn populate_with<L: VirtualMemoryLayout>(
+        &mut self,
+        layout: &L,
+        page_descriptor: fn(usize, &AttributeFields) -> Descriptor,
+    ) -> Result<(), &'static str> {
+        let max_virt_addr = layout.max_virt_addr_inclusive();
+
+        if max_virt_addr >> MAX_ADDR_SPACE_SHIFT != 0 {
+            return Err("Address space exceeds the translation table");
+        }
 
-                *l3_entry = PageDescriptor::from_output_addr(phys_output_addr, &attribute_fields);
+        // Start over with an empty pool.
+        for descriptor in self.tables.iter_mut().flatten() {
+            *descriptor = Descriptor::new_zeroed();
+        }
+        self.num_tables_used = 0;
+
+        let root = self.alloc_table()?;
+        let populate = Populate {
+            layout,
+            max_virt_addr,
+            page_descriptor,
+        };
+
+        self.populate_table(root, START_LEVEL, 0, &populate)?;
+
+        self.addr_space_size = (max_virt_addr + 1)
+            .max(1 << MIN_ADDR_SPACE_SHIFT)
+            .next_power_of_two();
+
+        Ok(())
+    }
+
+    /// Fills the table of the level, which translates the window starting at `window_start`.
+    /// Returns whether any address of the window is mapped.
+    fn populate_table<L: VirtualMemoryLayout>(
+        &mut self,
+        table_nr: usize,
+        level: usize,
+        window_start: usize,
+        populate: &Populate<L>,
+    ) -> Result<bool, &'static str> {
+        let mut mapped = false;
+
+        for idx in 0..ENTRIES {
+            let start = window_start + (idx << level_shift(level));
+
+            // The last window may extend beyond the layout.
+            if start > populate.max_virt_addr {
+                break;
+            }
+
+            let descriptor = self.populate_entry(level, start, populate)?;
+
+            mapped |= descriptor.is_valid();
+            self.tables[table_nr][idx] = descriptor;
+        }
+
+        Ok(mapped)
+    }
+
+    /// Returns the descriptor of the level, which translates the range starting at `start`.
+    ///
+    /// A range, which shares one translation and attributes, is translated by a block descriptor
+    /// if the level allows it. If the layout reports the range as uniform, only its first address
+    /// is looked up. Otherwise, a next level table is handed out, which is given back if it turns
+    /// out to be unmapped or uniform.
+    fn populate_entry<L: VirtualMemoryLayout>(
+        &mut self,
+        level: usize,
+        start: usize,
+        populate: &Populate<L>,
+    ) -> Result<Descriptor, &'static str> {
+        let shift = level_shift(level);
+
+        if level == PAGE_LEVEL {
+            return Ok(match populate.layout.virt_addr_properties(start)? {
+                Some((phys_output_addr, attribute_fields)) => {
+                    (populate.page_descriptor)(phys_output_addr, &attribute_fields)
+                }
+                None => Descriptor::new_zeroed(),
+            });
+        }
+
+        let end = start + ((1 << shift) - 1);
+
+        if level >= FIRST_BLOCK_LEVEL
+            && end <= populate.max_virt_addr
+            && populate.layout.is_uniform(start..=end)
+        {
+            match populate.layout.virt_addr_properties(start)? {
+                Some((phys_output_addr, attribute_fields))
+                    if phys_output_addr % (1 << shift) == 0 =>
+                {
+                    let page = (populate.page_descriptor)(phys_output_addr, &attribute_fields);
+                    return Ok(page.at_level(level));
+                }
+                // Unmapped, the descriptor stays invalid.
+                None => return Ok(Descriptor::new_zeroed()),
+                // The output is not aligned for a block, fall back to the next level.
+                Some(_) => (),
+            }
+        }
+
+        let table_nr = self.alloc_table()?;
+
+        if !self.populate_table(table_nr, level + 1, start, populate)? {
+            self.free_table(table_nr);
+            return Ok(Descriptor::new_zeroed());
+        }
+
+        if level >= FIRST_BLOCK_LEVEL {
+            if let Some(block) = self.merge_into_block(table_nr, level) {
+                self.free_table(table_nr);
+                return Ok(block);
+            }
+        }
+
+        Ok(Descriptor::from_next_lvl_table_addr(
+            self.tables[table_nr].phys_start_addr_usize(VIRT_OFFSET),
+        ))
+    }
+
+    /// Returns a block descriptor of the level if the next level table translates one contiguous
+    /// range with the same attributes, which is aligned for a block.
+    fn merge_into_block(&self, table_nr: usize, level: usize) -> Option<Descriptor> {
+        let table = &self.tables[table_nr];
+        let first = table[0];
+
+        if first.next_lvl_table_addr(level + 1).is_some() {
+            return None;
+        }
+
+        if first.output_addr()? % (1 << level_shift(level)) != 0 {
+            return None;
+        }
+
+        let contiguous = table
+            .iter()
+            .enumerate()
+            .all(|(n, descriptor)| descriptor.value == first.nth_next(n, level + 1).value);
+
+        if !contiguous {
+            return None;
+        }
+
+        Some(first.at_level(level))
+    }
+
+    /// Hands out the next free table from the pool.
+    fn alloc_table(&mut self) -> Result<usize, &'static str> {
+        if self.num_tables_used == NUM_TABLES {
+            return Err(OUT_OF_TABLES);
+        }
+
+        self.num_tables_used += 1;
+
+        Ok(self.num_tables_used - 1)
+    }
+
+    /// Gives the most recently handed out table back to the pool.
+    fn free_table(&mut self, table_nr: usize) {
+        debug_assert_eq!(table_nr + 1, self.num_tables_used);
+
+        for descriptor in self.tables[table_nr].iter_mut() {
+            *descriptor = Descriptor::new_zeroed();
+        }
+        self.num_tables_used -= 1;
+    }
+
+    /// Returns the number of the pool table at the address.
+    fn table_nr(&self, table_addr: usize) -> usize {
+        (table_addr - self.tables.phys_start_addr_usize(VIRT_OFFSET)) / Granule::SIZE
+    }
+
+    /// Walks the tables down to the descriptor, which translates the virtual address and is not a
+    /// table descriptor. Returns its table number, index and level.
+    fn walk(&self, virt_addr: usize) -> Result<(usize, usize, usize), &'static str> {
+        if virt_addr >= self.addr_space_size {
+            return Err("Virtual address out of range");
+        }
+
+        let mut table_nr = 0;
+        let mut level = START_LEVEL;
+
+        loop {
+            let idx = (virt_addr >> level_shift(level)) & (ENTRIES - 1);
+
+            match self.tables[table_nr][idx].next_lvl_table_addr(level) {
+                Some(table_addr) => {
+                    table_nr = self.table_nr(table_addr);
+                    level += 1;
+                }
+                None => return Ok((table_nr, idx, level)),
+            }
+        }
+    }
+
+    /// Replaces the block descriptors translating the virtual address by next level tables with
+    /// the same translation, down to the page level, so that individual pages can be changed.
+    /// Returns whether a block was split.
+    ///
+    /// `invalidate` is called after each block descriptor has been removed and before the table
+    /// descriptor is written, so that the caller can perform TLB maintenance as required by
+    /// break-before-make.
+    pub fn split_block(
+        &mut self,
+        virt_addr: usize,
+        mut invalidate: impl FnMut(),
+    ) -> Result<bool, &'static str> {
+        let mut split = false;
+
+        loop {
+            let (table_nr, idx, level) = self.walk(virt_addr)?;
+            let block = self.tables[table_nr][idx];
+
+            if level == PAGE_LEVEL || !block.is_valid() {
+                return Ok(split);
+            }
+
+            let next_table_nr = self.alloc_table()?;
+            let first = block.at_level(level + 1);
+
+            for (n, descriptor) in self.tables[next_table_nr].iter_mut().enumerate() {
+                *descriptor = first.nth_next(n, level + 1);
+            }
+
+            let table = Descriptor::from_next_lvl_table_addr(
+                self.tables[next_table_nr].phys_start_addr_usize(VIRT_OFFSET),
+            );
+
+            // Break.
+            self.tables[table_nr][idx] = Descriptor::new_zeroed();
+            invalidate();
+
+            // Make.
+            self.tables[table_nr][idx] = table;
+            split = true;
+        }
+    }
+
+    /// Returns the number of tables, which `split_block()` takes from the pool for the pages of the
+    /// range. With `allocate`, the tables `set_page()` takes for windows without one are included.
+    pub fn tables_needed(
+        &self,
+        virt_range: Range<usize>,
+        allocate: bool,
+    ) -> Result<usize, &'static str> {
+        let mut needed = 0;
+        let mut virt_addr = virt_range.start;
+
+        while virt_addr < virt_range.end {
+            let (table_nr, idx, level) = self.walk(virt_addr)?;
+            let window_end = (virt_addr | ((1 << level_shift(level)) - 1)) + 1;
+            let end = window_end.min(virt_range.end);
+
+            if level != PAGE_LEVEL && (allocate || self.tables[table_nr][idx].is_valid()) {
+                needed += tables_below(level, virt_addr..end);
+            }
+
+            virt_addr = end;
+        }
+
+        Ok(needed)
+    }
+
+    /// Returns the number of tables left in the pool.
+    pub fn free_tables(&self) -> usize {
+        NUM_TABLES - self.num_tables_used
+    }
+
+    /// Returns the page descriptor, which translates the virtual address, or `None` if a window
+    /// above it has no next level table. With `allocate`, free tables are handed out instead.
+    /// Blocks must be split with `split_block()` first.
+    fn page_descriptor_mut(
+        &mut self,
+        virt_addr: usize,
+        allocate: bool,
+    ) -> Result<Option<&mut Descriptor>, &'static str> {
+        loop {
+            let (table_nr, idx, level) = self.walk(virt_addr)?;
+
+            if level == PAGE_LEVEL {
+                return Ok(Some(&mut self.tables[table_nr][idx]));
+            }
+
+            if self.tables[table_nr][idx].is_valid() {
+                return Err("Virtual address is translated by a block descriptor");
+            }
+
+            if !allocate {
+                return Ok(None);
+            }
+
+            // Free tables only contain invalid descriptors.
+            let next_table_nr = self.alloc_table()?;
+            self.tables[table_nr][idx] = Descriptor::from_next_lvl_table_addr(
+                self.tables[next_table_nr].phys_start_addr_usize(VIRT_OFFSET),
+            );
+        }
+    }
+
+    /// Maps the page containing the virtual address to the physical output address.
+    ///
+    /// Only the descriptor is written. Break-before-make and TLB maintenance are up to the caller.
+    pub fn set_page(
+        &mut self,
+        virt_addr: usize,
+        phys_output_addr: usize,
+        attribute_fields: &AttributeFields,
+    ) -> Result<(), &'static str> {
+        let page = self
+            .page_descriptor_mut(virt_addr, true)?
+            .ok_or(OUT_OF_TABLES)?;
+
+        *page = Descriptor::from_output_addr(phys_output_addr, attribute_fields);
+
+        Ok(())
+    }
+
+    /// Unmaps the page containing the virtual address.
+    ///
+    /// Only the descriptor is written. TLB maintenance is up to the caller.
+    pub fn clear_page(&mut self, virt_addr: usize) -> Result<(), &'static str> {
+        if let Some(page) = self.page_descriptor_mut(virt_addr, false)? {
+            *page = Descriptor::new_zeroed();
+        }
+
+        Ok(())
+    }
+
+    /// Returns the physical output address of the page containing the virtual address, or `None`
+    /// if the page is unmapped.
+    pub fn page_output_addr(&self, virt_addr: usize) -> Result<Option<usize>, &'static str> {
+        let (table_nr, idx, level) = self.walk(virt_addr)?;
+
+        // Offset of the page within a block.
+        let offset = virt_addr & ((1 << level_shift(level)) - 1) & !(Granule::SIZE - 1);
+
+        Ok(self.tables[table_nr][idx]
+            .output_addr()
+            .map(|addr| addr + offset))
+    }
+
+    /// Returns the physical address and the attributes, which the stage 1 tables translate the
+    /// virtual address to, or `None` if it is unmapped. The tables are walked the same way as by
+    /// the MMU.
+    pub fn translate(
+        &self,
+        virt_addr: usize,
+    ) -> Result<Option<(usize, AttributeFields)>, &'static str> {
+        let (table_nr, idx, level) = self.walk(virt_addr)?;
+        let descriptor = self.tables[table_nr][idx];
+
+        // Offset within the block or page.
+        let offset = virt_addr & ((1 << level_shift(level)) - 1);
+
+        Ok(descriptor
+            .output_addr()
+            .map(|addr| (addr + offset, descriptor.attribute_fields())))
+    }
+
+    /// Calls `f` for the mapped ranges of the stage 1 tables in ascending order. Adjacent blocks
+    /// and pages are coalesced into one range if they are contiguous in physical memory and share
+    /// the attributes.
+    pub fn for_each_mapped_range<E>(
+        &self,
+        mut f: impl FnMut(&MappedRange) -> Result<(), E>,
+    ) -> Result<(), E> {
+        if self.num_tables_used == 0 {
+            return Ok(());
+        }
+
+        let mut current: Option<MappedRange> = None;
+
+        self.visit_leaves(0, START_LEVEL, 0, &mut |virt_start, size, descriptor| {
+            let next = descriptor.output_addr().map(|phys_start| MappedRange {
+                virt_start,
+                phys_start,
+                size,
+                attribute_fields: descriptor.attribute_fields(),
+            });
+
+            if let (Some(range), Some(next)) = (&mut current, &next) {
+                if range.virt_start + range.size == next.virt_start
+                    && range.phys_start + range.size == next.phys_start
+                    && range.attribute_fields == next.attribute_fields
+                {
+                    range.size += next.size;
+                    return Ok(());
+                }
+            }
+
+            if let Some(range) = current.take() {
+                f(&range)?;
+            }
+            current = next;
+
+            Ok(())
+        })?;
+
+        match current {
+            Some(range) => f(&range),
+            None => Ok(()),
+        }
+    }
+
+    /// Calls `visit` with the virtual start address and size of every descriptor of the table and
+    /// its next level tables, which is not a table descriptor.
+    fn visit_leaves<E, F>(
+        &self,
+        table_nr: usize,
+        level: usize,
+        window_start: usize,
+        visit: &mut F,
+    ) -> Result<(), E>
+    where
+        F: FnMut(usize, usize, &Descriptor) -> Result<(), E>,
+    {
+        for (idx, descriptor) in self.tables[table_nr].iter().enumerate() {
+            let start = window_start + (idx << level_shift(level));
+
+            match descriptor.next_lvl_table_addr(level) {
+                Some(table_addr) => {
+                    self.visit_leaves(self.table_nr(table_addr), level + 1, start, visit)?
+                }
+                None => visit(start, 1 << level_shift(level), descriptor)?,
             }
         }
 
         Ok(())
     }
 
+    /// Size of the populated address space, which determines T0SZ.
+    pub fn addr_space_size(&self) -> usize {
+        self.addr_space_size
+    }
+
     /// The translation table's base address to be used for programming the MMU.
     pub fn phys_base_address(&self) -> u64 {
-        self.lvl2.phys_start_addr_u64()
+        self.tables[0].phys_start_addr_u64(VIRT_OFFSET)
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Testing
+//--------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mmu::{layout::simple::SimpleMemoryLayout, Translation, TranslationDescriptor};
+
+    // Descriptor bits, spelled out independently of the register definitions above.
+    const VALID: u64 = 1 << 0;
+    const TYPE_PAGE: u64 = 1 << 1;
+    const AF: u64 = 1 << 10;
+    const PXN: u64 = 1 << 53;
+    const UXN: u64 = 1 << 54;
+    const OUTPUT_ADDR_MASK: u64 = 0x0000_FFFF_FFFF_F000;
+
+    const SH_OUTER: u64 = 0b10;
+    const SH_INNER: u64 = 0b11;
+
+    fn attr_indx(value: u64) -> u64 {
+        (value >> 2) & 0b111
+    }
+
+    fn ap(value: u64) -> u64 {
+        (value >> 6) & 0b11
+    }
+
+    fn sh(value: u64) -> u64 {
+        (value >> 8) & 0b11
+    }
+
+    fn attributes(
+        mem_attributes: MemAttributes,
+        acc_perms: AccessPermissions,
+        execute_never: bool,
+    ) -> AttributeFields {
+        AttributeFields {
+            mem_attributes,
+            acc_perms,
+            execute_never,
+            ..AttributeFields::default()
+        }
+    }
+
+    /// A layout with every kind of descriptor. Ranges are aligned to 64 KiB, so that it is valid
+    /// for both granules.
+    fn sample_layout() -> SimpleMemoryLayout<8> {
+        SimpleMemoryLayout::new(
+            0x1_FFFF_FFFF,
+            [
+                TranslationDescriptor {
+                    name: "Stack guard",
+                    virtual_range: || 0x7_0000..=0x7_FFFF,
+                    physical_range_translation: Translation::Unmapped,
+                    attribute_fields: AttributeFields::default(),
+                },
+                TranslationDescriptor {
+                    name: "Kernel code and RO data",
+                    virtual_range: || 0x8_0000..=0xF_FFFF,
+                    physical_range_translation: Translation::Identity,
+                    attribute_fields: attributes(
+                        MemAttributes::CacheableDRAM,
+                        AccessPermissions::ReadOnly,
+                        false,
+                    ),
+                },
+                TranslationDescriptor {
+                    name: "DMA descriptors",
+                    virtual_range: || 0x1000_0000..=0x1000_FFFF,
+                    physical_range_translation: Translation::Identity,
+                    attribute_fields: attributes(
+                        MemAttributes::NonCacheable,
+                        AccessPermissions::ReadWrite,
+                        true,
+                    ),
+                },
+                TranslationDescriptor {
+                    name: "Framebuffer",
+                    virtual_range: || 0x2000_0000..=0x2001_FFFF,
+                    physical_range_translation: Translation::Identity,
+                    attribute_fields: attributes(
+                        MemAttributes::WriteThrough,
+                        AccessPermissions::ReadWrite,
+                        true,
+                    ),
+                },
+                TranslationDescriptor {
+                    name: "EL0 task",
+                    virtual_range: || 0x3000_0000..=0x3000_FFFF,
+                    physical_range_translation: Translation::Identity,
+                    attribute_fields: AttributeFields {
+                        el0_access: true,
+                        el0_execute_never: false,
+                        ..AttributeFields::default()
+                    },
+                },
+                TranslationDescriptor {
+                    name: "Peripherals",
+                    virtual_range: || 0xFC00_0000..=0xFF7F_FFFF,
+                    physical_range_translation: Translation::Identity,
+                    attribute_fields: attributes(
+                        MemAttributes::Device,
+                        AccessPermissions::ReadWrite,
+                        true,
+                    ),
+                },
+                TranslationDescriptor {
+                    name: "Local peripherals",
+                    virtual_range: || 0xFF80_0000..=0xFFFF_FFFF,
+                    physical_range_translation: Translation::Identity,
+                    attribute_fields: attributes(
+                        MemAttributes::DeviceNGnRnE,
+                        AccessPermissions::ReadWrite,
+                        true,
+                    ),
+                },
+                TranslationDescriptor {
+                    name: "Remapped",
+                    virtual_range: || 0x1_0000_0000..=0x1_0001_FFFF,
+                    physical_range_translation: Translation::Offset(0x3000_0000),
+                    attribute_fields: AttributeFields::default(),
+                },
+            ],
+        )
+    }
+
+    type TestTable = FixedSizeTranslationTable<16, 0>;
+
+    /// Allocates an empty table on the heap, because the pool is too large for the stack of a
+    /// test thread. All zeroes is the empty state, the same as for the static of the runtime.
+    fn new_table<const NUM_TABLES: usize, const VIRT_OFFSET: usize>(
+    ) -> Box<FixedSizeTranslationTable<NUM_TABLES, VIRT_OFFSET>> {
+        let layout =
+            std::alloc::Layout::new::<FixedSizeTranslationTable<NUM_TABLES, VIRT_OFFSET>>();
+
+        unsafe { Box::from_raw(std::alloc::alloc_zeroed(layout) as *mut _) }
+    }
+
+    fn populated(layout: &impl VirtualMemoryLayout) -> Box<TestTable> {
+        let mut table = new_table();
+        table.populate_tt_entries(layout).unwrap();
+
+        table
+    }
+
+    /// Returns the raw value and level of the descriptor, which translates the virtual address.
+    fn leaf<const NUM_TABLES: usize>(
+        table: &FixedSizeTranslationTable<NUM_TABLES, 0>,
+        virt_addr: usize,
+    ) -> (u64, usize) {
+        let (table_nr, idx, level) = table.walk(virt_addr).unwrap();
+
+        (table.tables[table_nr][idx].value, level)
+    }
+
+    /// Checks the access permissions and execute-never bits of a stage 1 descriptor.
+    #[cfg(not(feature = "hypervisor"))]
+    fn assert_access(value: u64, read_only: bool, el0_access: bool, pxn: bool, uxn: bool) {
+        assert_eq!(ap(value), ((read_only as u64) << 1) | el0_access as u64);
+        assert_eq!(value & PXN != 0, pxn);
+        assert_eq!(value & UXN != 0, uxn);
+    }
+
+    /// In the EL2 translation regime, AP[1] is RES1 and the only XN bit is at the position of UXN.
+    #[cfg(feature = "hypervisor")]
+    fn assert_access(value: u64, read_only: bool, _el0_access: bool, pxn: bool, _uxn: bool) {
+        assert_eq!(ap(value), ((read_only as u64) << 1) | 1);
+        assert_eq!(value & PXN, 0);
+        assert_eq!(value & UXN != 0, pxn);
+    }
+
+    /// Checks the bits, which all valid block and page descriptors share.
+    fn assert_leaf(value: u64, level: usize, output_addr: usize) {
+        assert_ne!(value & VALID, 0, "descriptor is invalid");
+        assert_ne!(value & AF, 0, "access flag is clear");
+        assert_eq!(value & TYPE_PAGE != 0, level == PAGE_LEVEL);
+        assert_eq!(value & OUTPUT_ADDR_MASK, output_addr as u64);
+    }
+
+    #[test]
+    fn default_memory_is_cacheable_inner_shareable() {
+        let table = populated(&sample_layout());
+        let (value, level) = leaf(&table, 0x4000_0000);
+
+        assert_leaf(value, level, 0x4000_0000);
+        assert_eq!(sh(value), SH_INNER);
+        assert_eq!(attr_indx(value), mair::NORMAL);
+        assert_access(value, false, false, true, true);
+    }
+
+    #[test]
+    fn device_memory_is_outer_shareable() {
+        let table = populated(&sample_layout());
+
+        let (value, level) = leaf(&table, 0xFE20_0000);
+        assert_leaf(value, level, 0xFE20_0000 & !((1 << level_shift(level)) - 1));
+        assert_eq!(sh(value), SH_OUTER);
+        assert_eq!(attr_indx(value), mair::DEVICE);
+        assert_access(value, false, false, true, true);
+
+        let (value, level) = leaf(&table, 0xFF84_0000);
+        assert_leaf(value, level, 0xFF84_0000 & !((1 << level_shift(level)) - 1));
+        assert_eq!(sh(value), SH_OUTER);
+        assert_eq!(attr_indx(value), mair::DEVICE_NGNRNE);
+    }
+
+    #[test]
+    fn normal_memory_types() {
+        let table = populated(&sample_layout());
+
+        let (value, level) = leaf(&table, 0x1000_0000);
+        assert_leaf(value, level, 0x1000_0000);
+        assert_eq!(level, PAGE_LEVEL);
+        assert_eq!(sh(value), SH_INNER);
+        assert_eq!(attr_indx(value), mair::NORMAL_NON_CACHEABLE);
+
+        let (value, level) = leaf(&table, 0x2001_0000);
+        assert_leaf(value, level, 0x2001_0000);
+        assert_eq!(sh(value), SH_INNER);
+        assert_eq!(attr_indx(value), mair::NORMAL_WRITE_THROUGH);
+
+        // The pages right after the ranges are back to the default.
+        let (value, _) = leaf(&table, 0x1001_0000);
+        assert_eq!(attr_indx(value), mair::NORMAL);
+        let (value, _) = leaf(&table, 0x2002_0000);
+        assert_eq!(attr_indx(value), mair::NORMAL);
+    }
+
+    #[test]
+    fn kernel_code_is_read_only_and_executable() {
+        let table = populated(&sample_layout());
+        let (value, level) = leaf(&table, 0x9_0000);
+
+        assert_eq!(level, PAGE_LEVEL);
+        assert_leaf(value, level, 0x9_0000);
+        assert_eq!(sh(value), SH_INNER);
+        assert_eq!(attr_indx(value), mair::NORMAL);
+        assert_access(value, true, false, false, true);
+    }
+
+    #[test]
+    fn el0_access_and_execute() {
+        let table = populated(&sample_layout());
+        let (value, level) = leaf(&table, 0x3000_0000);
+
+        assert_leaf(value, level, 0x3000_0000);
+        assert_access(value, false, true, true, false);
+    }
+
+    #[test]
+    fn offset_translation() {
+        let table = populated(&sample_layout());
+        let (value, level) = leaf(&table, 0x1_0001_0000);
+
+        assert_eq!(level, PAGE_LEVEL);
+        assert_leaf(value, level, 0x3001_0000);
+
+        let (phys_addr, attribute_fields) = table.translate(0x1_0001_1234).unwrap().unwrap();
+        assert_eq!(phys_addr, 0x3001_1234);
+        assert!(attribute_fields == AttributeFields::default());
+    }
+
+    #[test]
+    fn unmapped_range_is_invalid() {
+        let table = populated(&sample_layout());
+
+        assert_eq!(leaf(&table, 0x7_0000).0, 0);
+        assert!(table.translate(0x7_FFFF).unwrap().is_none());
+
+        // The neighbours stay mapped.
+        assert!(table.translate(0x6_FFFF).unwrap().is_some());
+        assert!(table.translate(0x8_0000).unwrap().is_some());
+    }
+
+    #[test]
+    fn uniform_ranges_are_translated_by_blocks() {
+        let table = populated(&sample_layout());
+
+        // Window without descriptors.
+        let (value, level) = leaf(&table, 0x4000_0000);
+        assert!(level < PAGE_LEVEL);
+        assert_eq!(value & TYPE_PAGE, 0);
+
+        // Window with descriptors.
+        assert_eq!(leaf(&table, 0).1, PAGE_LEVEL);
+    }
+
+    #[test]
+    fn translate_matches_layout() {
+        let layout = sample_layout();
+        let table = populated(&layout);
+
+        let step = 0x10_0000 - 0x1_0000;
+        for virt_addr in (0..=layout.max_virt_addr_inclusive()).step_by(step) {
+            let translated = table.translate(virt_addr).unwrap();
+            let expected = layout.virt_addr_properties(virt_addr).unwrap();
+
+            #[cfg(feature = "hypervisor")]
+            let expected = expected.map(|(phys_addr, attribute_fields)| {
+                let attribute_fields = AttributeFields {
+                    el0_access: false,
+                    el0_execute_never: true,
+                    ..attribute_fields
+                };
+                (phys_addr, attribute_fields)
+            });
+
+            assert!(translated == expected, "mismatch at {:#x}", virt_addr);
+        }
+    }
+
+    #[test]
+    fn descriptors_hold_physical_table_addresses() {
+        const VIRT_OFFSET: usize = 0x1_0000;
+
+        let mut table = new_table::<16, VIRT_OFFSET>();
+        table.populate_tt_entries(&sample_layout()).unwrap();
+
+        let tables_addr = table.tables.as_ptr() as u64;
+        assert_eq!(table.phys_base_address(), tables_addr - VIRT_OFFSET as u64);
+
+        // The walk follows the table descriptors back to the virtual addresses of the tables.
+        let reference = populated(&sample_layout());
+        for &virt_addr in &[0, 0x9_0000, 0x1_0001_1234, 0xFE20_0000] {
+            assert!(table.translate(virt_addr).unwrap() == reference.translate(virt_addr).unwrap());
+        }
+    }
+
+    #[test]
+    fn address_space_is_rounded_up() {
+        let table = populated(&sample_layout());
+
+        let size = table.addr_space_size();
+
+        assert!(size.is_power_of_two());
+        assert!(size >= 0x2_0000_0000);
+        assert!(table.walk(size - 1).is_ok());
+        assert!(table.walk(size).is_err());
+    }
+
+    #[test]
+    fn oversized_address_space_is_rejected() {
+        let layout = SimpleMemoryLayout::new(1 << MAX_ADDR_SPACE_SHIFT, []);
+        let mut table = new_table::<1, 0>();
+
+        assert!(table.populate_tt_entries(&layout).is_err());
+    }
+
+    #[test]
+    fn pool_exhaustion_is_reported() {
+        let mut table = new_table::<2, 0>();
+
+        assert_eq!(
+            table.populate_tt_entries(&sample_layout()).err(),
+            Some("Not enough translation tables")
+        );
+    }
+
+    #[test]
+    fn split_block_keeps_attributes() {
+        let mut table = populated(&sample_layout());
+        let (block, block_level) = leaf(&table, 0x4000_0000);
+        assert!(block_level < PAGE_LEVEL);
+
+        // One split per level down to the pages.
+        let mut invalidations = 0;
+        assert!(table
+            .split_block(0x4001_0000, || invalidations += 1)
+            .unwrap());
+        assert_eq!(invalidations, PAGE_LEVEL - block_level);
+
+        let (value, level) = leaf(&table, 0x4001_0000);
+        assert_eq!(level, PAGE_LEVEL);
+        assert_leaf(value, level, 0x4001_0000);
+        assert_eq!(
+            value & !OUTPUT_ADDR_MASK,
+            (block & !OUTPUT_ADDR_MASK) | TYPE_PAGE
+        );
+    }
+
+    #[test]
+    fn tables_needed_matches_split() {
+        let mut table = populated(&sample_layout());
+        let (_, block_level) = leaf(&table, 0x4000_0000);
+        let page = 0x4001_0000..0x4001_0000 + Granule::SIZE;
+
+        let needed = table.tables_needed(page.clone(), false).unwrap();
+        assert_eq!(needed, PAGE_LEVEL - block_level);
+
+        // Pages in two windows of the next level take a table each.
+        let next_level_window = 1 << level_shift(block_level + 1);
+        let two_windows = 0x4000_0000..0x4000_0000 + next_level_window + Granule::SIZE;
+        assert_eq!(
+            table.tables_needed(two_windows, false).unwrap(),
+            needed + (PAGE_LEVEL - block_level - 1)
+        );
+
+        let free_tables = table.free_tables();
+        table.split_block(page.start, || {}).unwrap();
+        assert_eq!(free_tables - table.free_tables(), needed);
+
+        assert_eq!(table.tables_needed(page, true).unwrap(), 0);
+    }
+
+    #[test]
+    fn stage2_device_memory() {
+        let mut table = new_table::<16, 0>();
+        table.populate_stage2_tt_entries(&sample_layout()).unwrap();
+
+        let (value, level) = leaf(&table, 0xFE20_0000);
+        assert_leaf(value, level, 0xFE20_0000 & !((1 << level_shift(level)) - 1));
+        assert_eq!(sh(value), SH_OUTER);
+        // MemAttr Device-nGnRE, S2AP read/write and XN.
+        assert_eq!((value >> 2) & 0b1111, 0b0001);
+        assert_eq!(ap(value), 0b11);
+        assert_ne!(value & UXN, 0);
+
+        let (value, _) = leaf(&table, 0x4000_0000);
+        assert_eq!(sh(value), SH_INNER);
+        assert_eq!((value >> 2) & 0b1111, 0b1111);
     }
 }
@@ -0,0 +1,182 @@
+//! Fault tolerant memory accesses for probing MMIO.
+//!
+//! Each probing access registers its instruction address together with a landing pad in the
+//! `.exception_fixup` linker section. When the access causes a synchronous data abort, the default
+//! exception handler finds the faulting instruction in the table and resumes execution at the
+//! landing pad, which turns the abort into an `Err`. Applications overriding the
+//! `current_elx_synchronous` exception handler must call `fixup_exception()` first.
+//!
+//! Only synchronous aborts, such as translation faults or synchronous external aborts, can be
+//! recovered. Asynchronous SErrors are not attributable to a single access.
+
+use core::{
+    cell::UnsafeCell,
+    fmt, slice,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use crate::{
+    core_id, elx,
+    exception::{
+        exception::ExceptionContext,
+        syndrome::{ExceptionClass, FaultStatus, Iss, Syndrome},
+    },
+    NUM_CORES,
+};
+
+/// An entry of the exception fixup table.
+#[repr(C)]
+struct FixupEntry {
+    /// Address of the instruction, which is allowed to fault.
+    insn: u64,
+
+    /// Address at which execution resumes if the instruction faults.
+    landing_pad: u64,
+}
+
+// Symbols from the linker script.
+extern "Rust" {
+    static __exception_fixup_start: UnsafeCell<FixupEntry>;
+    static __exception_fixup_end_exclusive: UnsafeCell<FixupEntry>;
+}
+
+/// Syndrome of the last recovered abort of each core, indexed by core number. Saved by the
+/// exception handler, because a nested exception may overwrite the syndrome register before the
+/// landing pad reads it.
+static FAULT_SYNDROMES: [AtomicU64; NUM_CORES] = [
+    AtomicU64::new(0),
+    AtomicU64::new(0),
+    AtomicU64::new(0),
+    AtomicU64::new(0),
+];
+
+/// A fault, which occurred during a probing access.
+#[derive(Clone, Copy)]
+pub struct Fault {
+    /// Raw ESR_EL1 (ESR_EL2 with the `hypervisor` feature) value of the abort.
+    esr: u64,
+}
+
+impl Fault {
+    /// The exception syndrome of the abort.
+    pub fn syndrome(&self) -> Syndrome {
+        Syndrome::from_raw(self.esr)
+    }
+
+    /// The data fault status code of the abort.
+    pub fn status(&self) -> Option<FaultStatus> {
+        match self.syndrome().decode() {
+            Iss::DataAbort(da) => Some(da.status),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Debug for Fault {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.status() {
+            Some(status) => write!(f, "Fault({})", status),
+            None => write!(f, "Fault(ESR_{}: {:#010x})", elx::NAME, self.esr),
+        }
+    }
+}
+
+/// Returns the exception fixup table.
+fn fixup_table() -> &'static [FixupEntry] {
+    unsafe {
+        let start = __exception_fixup_start.get() as *const FixupEntry;
+        let end = __exception_fixup_end_exclusive.get() as *const FixupEntry;
+
+        slice::from_raw_parts(start, end.offset_from(start) as usize)
+    }
+}
+
+/// If the exception is a data abort caused by a registered probing access, redirect execution to
+/// its landing pad. Returns true if the exception was handled.
+///
+/// Called by `default_exception_handler()`. A custom `current_elx_synchronous` handler must call it
+/// before anything else and return if it returns true, otherwise probing accesses do not recover.
+pub fn fixup_exception(e: &mut ExceptionContext) -> bool {
+    let syndrome = Syndrome::read();
+
+    if syndrome.exception_class() != ExceptionClass::DataAbortCurrentEL {
+        return false;
+    }
+
+    match fixup_table().iter().find(|f| f.insn == e.elr_el1()) {
+        Some(fixup) => {
+            FAULT_SYNDROMES[core_id() as usize].store(syndrome.raw(), Ordering::Relaxed);
+            e.set_elr_el1(fixup.landing_pad);
+            true
+        }
+        None => false,
+    }
+}
+
+/// The fault of the last probing access of the executing core, which was recovered.
+fn last_fault() -> Fault {
+    Fault {
+        esr: FAULT_SYNDROMES[core_id() as usize].load(Ordering::Relaxed),
+    }
+}
+
+/// Reads a u32 from the address, returning an error instead of panicking if the access aborts.
+pub fn read_u32(addr: usize) -> Result<u32, Fault> {
+    let value: u32;
+    let faulted: u64;
+
+    unsafe {
+        asm!(
+            "mov {faulted}, #0",
+            "1: ldr {value:w}, [{addr}]",
+            "b 3f",
+            // Landing pad.
+            "2: mov {faulted}, #1",
+            "3:",
+            ".pushsection .exception_fixup, \"a\"",
+            ".balign 8",
+            ".quad 1b, 2b",
+            ".popsection",
+            addr = in(reg) addr,
+            value = lateout(reg) value,
+            faulted = out(reg) faulted,
+            options(nostack, readonly)
+        );
+    }
+
+    if faulted != 0 {
+        Err(last_fault())
+    } else {
+        Ok(value)
+    }
+}
+
+/// Writes a u32 to the address, returning an error instead of panicking if the access aborts.
+pub fn write_u32(addr: usize, value: u32) -> Result<(), Fault> {
+    let faulted: u64;
+
+    unsafe {
+        asm!(
+            "mov {faulted}, #0",
+            "1: str {value:w}, [{addr}]",
+            "b 3f",
+            // Landing pad.
+            "2: mov {faulted}, #1",
+            "3:",
+            ".pushsection .exception_fixup, \"a\"",
+            ".balign 8",
+            ".quad 1b, 2b",
+            ".popsection",
+            addr = in(reg) addr,
+            value = in(reg) value,
+            faulted = out(reg) faulted,
+            options(nostack)
+        );
+    }
+
+    if faulted != 0 {
+        Err(last_fault())
+    } else {
+        Ok(())
+    }
+}
@@ -0,0 +1,78 @@
+//! Secondary core bring-up through the RPi4 firmware spin table.
+//!
+//! The firmware armstub parks all non-boot cores in a loop, which polls a per-core release address
+//! and jumps to it once it becomes non-zero. Released cores go through the same EL2 to EL1
+//! transition, exception vector setup and MMU enable as the boot core before calling the provided
+//! entry function.
+
+use core::sync::atomic::{AtomicUsize, Ordering};
+use cortex_a::asm;
+
+use crate::{cache, core_id, entry::BOOT_CORE_ID, memory, NUM_CORES};
+
+/// Release addresses polled by the firmware armstub, indexed by core number.
+const SPIN_TABLE: [usize; NUM_CORES] = [0xD8, 0xE0, 0xE8, 0xF0];
+
+/// Entry function value, which indicates that the core was not started
+const NOT_STARTED: usize = 0;
+
+/// Entry functions of secondary cores, indexed by core number.
+static ENTRIES: [AtomicUsize; NUM_CORES] = [
+    AtomicUsize::new(NOT_STARTED),
+    AtomicUsize::new(NOT_STARTED),
+    AtomicUsize::new(NOT_STARTED),
+    AtomicUsize::new(NOT_STARTED),
+];
+
+// Entry point of secondary cores in boot.s
+extern "C" {
+    fn _start_secondary() -> !;
+}
+
+/// Releases a secondary core from the firmware spin table. The core calls `entry` with its core
+/// number once the runtime is initialized.
+///
+/// Must be called after the MMU is enabled on the boot core, because secondary cores reuse its
+/// translation tables. This is always the case when called from `main()`.
+pub fn start_core(core: u8, entry: fn(u8) -> !) {
+    if core as usize >= NUM_CORES || core as u64 == BOOT_CORE_ID {
+        panic!("Attempted to start invalid secondary core {}", core);
+    }
+
+    if ENTRIES[core as usize].swap(entry as usize, Ordering::AcqRel) != NOT_STARTED {
+        panic!("Attempted to start core {}, which is already running", core);
+    }
+
+    // The parked core runs with the MMU off, so it is released to the physical address.
+    let release_addr = memory::phys_to_virt(SPIN_TABLE[core as usize]);
+    let entry_addr = memory::virt_to_phys(_start_secondary as *const () as usize);
+
+    unsafe {
+        core::ptr::write_volatile(release_addr as *mut u64, entry_addr as u64);
+    }
+
+    // The parked core polls the spin table with caches disabled, so the release address must be
+    // pushed to the point of coherency. This also ensures that the release address is written
+    // before waking up the parked cores.
+    cache::clean_invalidate_dcache_range(release_addr..release_addr + 8);
+    asm::sev();
+}
+
+/// Releases all secondary cores, which call `entry` with their core number.
+pub fn start_secondary_cores(entry: fn(u8) -> !) {
+    for core in (0..NUM_CORES as u8).filter(|&core| core as u64 != BOOT_CORE_ID) {
+        start_core(core, entry);
+    }
+}
+
+/// Calls the entry function registered for the executing core.
+///
+/// # Safety
+///
+/// - Must only be called once per core from the secondary core entry code.
+pub(crate) unsafe fn secondary_main() -> ! {
+    let entry = ENTRIES[core_id() as usize].load(Ordering::Acquire);
+    let entry = core::mem::transmute::<usize, fn(u8) -> !>(entry);
+
+    entry(core_id())
+}
@@ -0,0 +1,254 @@
+//! Supervisor call (SVC) dispatch for EL0 code.
+//!
+//! EL0 code invokes syscall `N` with `svc #N`, passing up to six arguments in x0-x5. The value
+//! returned by the registered handler is placed in x0 on return to EL0.
+//!
+//! With the `hypervisor` feature, the EL1 guest invokes syscall `N` with `hvc #N` instead.
+//!
+//! Other exceptions taken from EL0, such as aborts or undefined instructions, are faults of the
+//! task and are passed to the handler registered with `set_fault_handler()`. Without one, the fault
+//! is recorded, see `halted_fault()`, and the executing core halts, while the other cores keep
+//! running.
+//!
+//! ```ignore
+//! fn write_handler(args: [u64; 6]) -> u64 { /* ... */ }
+//!
+//! syscall::register(1, write_handler).unwrap();
+//!
+//! // In EL0 code
+//! asm!("svc #1", inlateout("x0") buf => ret, in("x1") len);
+//! ```
+
+use core::{
+    cell::UnsafeCell,
+    fmt,
+    sync::atomic::{AtomicBool, AtomicUsize, Ordering},
+};
+
+use cortex_a::asm;
+
+use crate::{
+    core_id, elx,
+    exception::{
+        exception::ExceptionContext,
+        syndrome::{Iss, Syndrome},
+    },
+    NUM_CORES,
+};
+
+/// Number of syscalls which can be registered.
+pub const MAX_SYSCALLS: usize = 64;
+
+/// Value returned in x0 for syscalls without a registered handler.
+pub const ENOSYS: u64 = u64::MAX;
+
+/// Syscall handler, which receives arguments x0-x5 and returns the value for x0.
+pub type SyscallHandler = fn(args: [u64; 6]) -> u64;
+
+/// Fault handler, which receives the fault and the context of the faulting task. The task resumes
+/// with the context if the handler returns, e.g. after the handler redirected it.
+pub type FaultHandler = fn(fault: TaskFault, e: &mut ExceptionContext);
+
+/// An exception taken from EL0 (the guest with the `hypervisor` feature), which is not a syscall.
+#[derive(Clone, Copy)]
+pub struct TaskFault {
+    /// Raw ESR_EL1 (ESR_EL2 with the `hypervisor` feature) value.
+    esr: u64,
+
+    /// Raw FAR_EL1 (FAR_EL2 with the `hypervisor` feature) value.
+    far: u64,
+
+    /// Address of the faulting instruction.
+    elr: u64,
+}
+
+impl TaskFault {
+    /// The exception syndrome of the fault.
+    pub fn syndrome(&self) -> Syndrome {
+        Syndrome::from_raw(self.esr)
+    }
+
+    /// The faulting virtual address of aborts, UNKNOWN for other exceptions.
+    pub fn far(&self) -> u64 {
+        self.far
+    }
+
+    /// Address of the faulting instruction.
+    pub fn elr(&self) -> u64 {
+        self.elr
+    }
+}
+
+impl fmt::Display for TaskFault {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let syndrome = self.syndrome();
+
+        writeln!(f, "Task fault at {:#018x}", self.elr)?;
+        writeln!(f, "FAR_{}: {:#018x}", elx::NAME, self.far)?;
+        writeln!(
+            f,
+            "ESR_{}: {:#010x} - {}",
+            elx::NAME,
+            self.esr,
+            syndrome.exception_class()
+        )?;
+        write!(f, "{}", syndrome.decode())
+    }
+}
+
+/// Syscall registration error variants.
+#[derive(Debug)]
+pub enum RegisterError {
+    /// Syscall number is not below `MAX_SYSCALLS`.
+    InvalidNumber,
+    AlreadyRegistered,
+}
+
+/// Handler value, which indicates that the syscall is not registered
+const NO_HANDLER: usize = 0;
+
+#[allow(clippy::declare_interior_mutable_const)]
+const NO_HANDLER_INIT: AtomicUsize = AtomicUsize::new(NO_HANDLER);
+
+/// Syscall handler function pointers, indexed by syscall number.
+static HANDLERS: [AtomicUsize; MAX_SYSCALLS] = [NO_HANDLER_INIT; MAX_SYSCALLS];
+
+/// The registered fault handler.
+static FAULT_HANDLER: AtomicUsize = AtomicUsize::new(NO_HANDLER);
+
+/// Faults which halted a core, indexed by core number. Each entry is written once by its core,
+/// which then publishes it through the matching flag.
+struct HaltedFaults {
+    published: [AtomicBool; NUM_CORES],
+    faults: UnsafeCell<[TaskFault; NUM_CORES]>,
+}
+
+unsafe impl Sync for HaltedFaults {}
+
+const NO_FAULT: TaskFault = TaskFault {
+    esr: 0,
+    far: 0,
+    elr: 0,
+};
+
+static HALTED_FAULTS: HaltedFaults = HaltedFaults {
+    published: [
+        AtomicBool::new(false),
+        AtomicBool::new(false),
+        AtomicBool::new(false),
+        AtomicBool::new(false),
+    ],
+    faults: UnsafeCell::new([NO_FAULT; NUM_CORES]),
+};
+
+/// Registers the handler of syscall `num`.
+pub fn register(num: u16, handler: SyscallHandler) -> Result<(), RegisterError> {
+    let slot = HANDLERS
+        .get(num as usize)
+        .ok_or(RegisterError::InvalidNumber)?;
+
+    slot.compare_exchange(
+        NO_HANDLER,
+        handler as usize,
+        Ordering::AcqRel,
+        Ordering::Acquire,
+    )
+    .map(|_| ())
+    .map_err(|_| RegisterError::AlreadyRegistered)
+}
+
+/// Removes the handler of syscall `num`.
+pub fn unregister(num: u16) {
+    if let Some(slot) = HANDLERS.get(num as usize) {
+        slot.store(NO_HANDLER, Ordering::Release);
+    }
+}
+
+/// Registers the handler of task faults, replacing the previous one.
+pub fn set_fault_handler(handler: FaultHandler) {
+    FAULT_HANDLER.store(handler as usize, Ordering::Release);
+}
+
+/// Removes the handler of task faults, so that faulting cores halt again.
+pub fn clear_fault_handler() {
+    FAULT_HANDLER.store(NO_HANDLER, Ordering::Release);
+}
+
+/// The fault which halted the core, if any.
+pub fn halted_fault(core: usize) -> Option<TaskFault> {
+    let published = HALTED_FAULTS.published.get(core)?;
+
+    if published.load(Ordering::Acquire) {
+        Some(unsafe { (*HALTED_FAULTS.faults.get())[core] })
+    } else {
+        None
+    }
+}
+
+/// Records the fault and halts the executing core. Interrupts stay masked, because the exception
+/// entry masked them.
+fn halt(fault: TaskFault) -> ! {
+    let core = core_id() as usize;
+
+    unsafe { (*HALTED_FAULTS.faults.get())[core] = fault };
+    HALTED_FAULTS.published[core].store(true, Ordering::Release);
+
+    loop {
+        asm::wfe();
+    }
+}
+
+/// Passes the fault, which caused the exception, to the fault handler or halts the executing core.
+fn task_fault(syndrome: Syndrome, e: &mut ExceptionContext) {
+    let fault = TaskFault {
+        esr: syndrome.raw(),
+        far: elx::far(),
+        elr: e.elr_el1(),
+    };
+
+    match FAULT_HANDLER.load(Ordering::Acquire) {
+        NO_HANDLER => halt(fault),
+        handler => {
+            let handler = unsafe { core::mem::transmute::<usize, FaultHandler>(handler) };
+
+            handler(fault, e)
+        }
+    }
+}
+
+/// Calls the handler of the syscall, which caused the exception.
+///
+/// Exceptions other than SVC (HVC with the `hypervisor` feature) are faults of the task, which are
+/// passed to the fault handler, see `set_fault_handler()`.
+pub fn dispatch(e: &mut ExceptionContext) {
+    let syndrome = Syndrome::read();
+
+    let num = match syndrome.decode() {
+        #[cfg(not(feature = "hypervisor"))]
+        Iss::Svc(num) => num as usize,
+        #[cfg(feature = "hypervisor")]
+        Iss::Hvc(num) => num as usize,
+        _ => return task_fault(syndrome, e),
+    };
+
+    let handler = HANDLERS
+        .get(num)
+        .map_or(NO_HANDLER, |slot| slot.load(Ordering::Acquire));
+
+    let ret = match handler {
+        NO_HANDLER => ENOSYS,
+        handler => {
+            let handler = unsafe { core::mem::transmute::<usize, SyscallHandler>(handler) };
+            let mut args = [0; 6];
+
+            for (i, arg) in args.iter_mut().enumerate() {
+                *arg = e.gpr(i);
+            }
+
+            handler(args)
+        }
+    };
+
+    // ELR_ELx already points to the instruction following SVC or HVC.
+    e.set_gpr(0, ret);
+}
@@ -0,0 +1,479 @@
+//! Flattened Device Tree (FDT) parser.
+//!
+//! Parses the device tree blob (DTB) passed by the firmware in place, without allocating. Supports
+//! looking up nodes by path or compatible string and decoding the standard `reg`, `ranges` and
+//! `interrupts` properties.
+//!
+//! ```ignore
+//! let fdt = unsafe { Fdt::from_ptr(dtb_addr as *const u8) }?;
+//!
+//! let bootargs = fdt.chosen_bootargs();
+//! let ram = fdt.memory().next();
+//!
+//! let soc = fdt.find_node("/soc").unwrap();
+//! let uart = fdt.find_compatible("arm,pl011").unwrap();
+//! let uart_base = soc.translate_address(uart.reg().next().unwrap().address);
+//! ```
+//!
+//! # Resources
+//!
+//! - https://github.com/devicetree-org/devicetree-specification/releases (chapter 5, "Flattened
+//!   Devicetree (DTB) Format")
+
+#![cfg_attr(not(test), no_std)]
+
+mod node;
+mod property;
+
+pub use node::{Cells, Children, Node, Properties, Range, Ranges, Region, Regs};
+pub use property::{Property, StrList, U32Iter};
+
+use core::{fmt, slice};
+
+/// Magic number at the start of every device tree blob.
+const FDT_MAGIC: u32 = 0xd00d_feed;
+
+/// Last version, which is compatible with version 17 parsed by this crate.
+const FDT_LAST_COMP_VERSION: u32 = 16;
+
+/// Size of the device tree header in bytes.
+const HEADER_SIZE: usize = 40;
+
+// Structure block tokens.
+const FDT_BEGIN_NODE: u32 = 0x1;
+const FDT_END_NODE: u32 = 0x2;
+const FDT_PROP: u32 = 0x3;
+const FDT_NOP: u32 = 0x4;
+
+/// Device tree parsing error variants.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error {
+    /// The blob does not start with the FDT magic number.
+    BadMagic,
+    /// The blob is not compatible with version 17 of the format.
+    UnsupportedVersion,
+    /// A block lies outside of the blob.
+    Truncated,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::BadMagic => write!(f, "Bad device tree magic"),
+            Error::UnsupportedVersion => write!(f, "Unsupported device tree version"),
+            Error::Truncated => write!(f, "Device tree is truncated"),
+        }
+    }
+}
+
+/// A parsed device tree blob.
+#[derive(Clone, Copy)]
+pub struct Fdt<'a> {
+    data: &'a [u8],
+    structs: &'a [u8],
+    strings: &'a [u8],
+}
+
+/// Reads a big endian u32 at the offset.
+fn be_u32(data: &[u8], offset: usize) -> Option<u32> {
+    let bytes = data.get(offset..offset.checked_add(4)?)?;
+
+    Some(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+}
+
+/// Rounds the offset up to the next token boundary.
+const fn align4(offset: usize) -> usize {
+    (offset + 3) & !3
+}
+
+impl<'a> Fdt<'a> {
+    /// Parses the header of the device tree blob in `data`.
+    pub fn new(data: &'a [u8]) -> Result<Self, Error> {
+        let header = |field: usize| be_u32(data, field * 4).ok_or(Error::Truncated);
+
+        if header(0)? != FDT_MAGIC {
+            return Err(Error::BadMagic);
+        }
+
+        let total_size = header(1)? as usize;
+        let off_dt_struct = header(2)? as usize;
+        let off_dt_strings = header(3)? as usize;
+        let last_comp_version = header(6)?;
+        let size_dt_strings = header(8)? as usize;
+        let size_dt_struct = header(9)? as usize;
+
+        if last_comp_version > FDT_LAST_COMP_VERSION {
+            return Err(Error::UnsupportedVersion);
+        }
+
+        let data = data.get(..total_size).ok_or(Error::Truncated)?;
+        let block = |offset: usize, size: usize| {
+            let end = offset.checked_add(size).ok_or(Error::Truncated)?;
+            data.get(offset..end).ok_or(Error::Truncated)
+        };
+
+        Ok(Self {
+            data,
+            structs: block(off_dt_struct, size_dt_struct)?,
+            strings: block(off_dt_strings, size_dt_strings)?,
+        })
+    }
+
+    /// Parses the device tree blob at `ptr`, whose size is taken from its header.
+    ///
+    /// # Safety
+    ///
+    /// - `ptr` must point to readable memory of at least the header size, and of the total size
+    ///   from the header if the magic number matches. The memory must not be modified for `'a`.
+    pub unsafe fn from_ptr(ptr: *const u8) -> Result<Self, Error> {
+        let header = slice::from_raw_parts(ptr, HEADER_SIZE);
+
+        if be_u32(header, 0) != Some(FDT_MAGIC) {
+            return Err(Error::BadMagic);
+        }
+
+        let total_size = be_u32(header, 4).ok_or(Error::Truncated)? as usize;
+
+        Self::new(slice::from_raw_parts(ptr, total_size.max(HEADER_SIZE)))
+    }
+
+    /// The total size of the blob in bytes.
+    pub fn total_size(&self) -> usize {
+        self.data.len()
+    }
+
+    /// The root node.
+    pub fn root(&self) -> Node<'a> {
+        // The structure block starts with the root node, possibly preceded by NOPs.
+        let mut offset = 0;
+        while be_u32(self.structs, offset) == Some(FDT_NOP) {
+            offset += 4;
+        }
+
+        Node::new(*self, offset, Cells::default()).unwrap_or_else(|| Node::empty(*self))
+    }
+
+    /// Finds a node by its absolute path, such as `/soc/serial@7e201000`.
+    ///
+    /// Path components without a unit address match nodes with any unit address, so `/memory`
+    /// finds `/memory@0`.
+    pub fn find_node(&self, path: &str) -> Option<Node<'a>> {
+        let mut node = self.root();
+
+        for component in path.split('/').filter(|c| !c.is_empty()) {
+            node = node.child(component)?;
+        }
+
+        Some(node)
+    }
+
+    /// Finds the first node whose `compatible` property contains `compatible`.
+    pub fn find_compatible(&self, compatible: &str) -> Option<Node<'a>> {
+        fn search<'a>(node: Node<'a>, compatible: &str) -> Option<Node<'a>> {
+            if node.compatible().any(|c| c == compatible) {
+                return Some(node);
+            }
+
+            node.children().find_map(|child| search(child, compatible))
+        }
+
+        search(self.root(), compatible)
+    }
+
+    /// The `/chosen/bootargs` property, which holds the kernel command line.
+    pub fn chosen_bootargs(&self) -> Option<&'a str> {
+        self.find_node("/chosen")?.property("bootargs")?.as_str()
+    }
+
+    /// The regions of the `/memory` node.
+    pub fn memory(&self) -> Regs<'a> {
+        match self.find_node("/memory") {
+            Some(memory) => memory.reg(),
+            None => Regs::empty(),
+        }
+    }
+
+    /// Returns the NUL terminated string at `offset` of the strings block.
+    fn string(&self, offset: usize) -> Option<&'a str> {
+        cstr(self.strings.get(offset..)?)
+    }
+}
+
+/// Returns the NUL terminated string at the start of `data`, without the terminator.
+fn cstr(data: &[u8]) -> Option<&str> {
+    let len = data.iter().position(|&b| b == 0)?;
+
+    core::str::from_utf8(&data[..len]).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FDT_END: u32 = 0x9;
+    const MEM_RSVMAP_SIZE: usize = 16;
+
+    /// Builds a device tree blob with an empty memory reservation map.
+    #[derive(Default)]
+    struct Builder {
+        structs: Vec<u8>,
+        strings: Vec<u8>,
+    }
+
+    impl Builder {
+        fn token(&mut self, token: u32) -> &mut Self {
+            self.structs.extend_from_slice(&token.to_be_bytes());
+            self
+        }
+
+        fn pad(&mut self) {
+            self.structs.resize(align4(self.structs.len()), 0);
+        }
+
+        fn begin_node(&mut self, name: &str) -> &mut Self {
+            self.token(FDT_BEGIN_NODE);
+            self.structs.extend_from_slice(name.as_bytes());
+            self.structs.push(0);
+            self.pad();
+            self
+        }
+
+        fn end_node(&mut self) -> &mut Self {
+            self.token(FDT_END_NODE)
+        }
+
+        fn prop(&mut self, name: &str, value: &[u8]) -> &mut Self {
+            let name_offset = self.strings.len() as u32;
+            self.strings.extend_from_slice(name.as_bytes());
+            self.strings.push(0);
+
+            self.token(FDT_PROP)
+                .token(value.len() as u32)
+                .token(name_offset);
+            self.structs.extend_from_slice(value);
+            self.pad();
+            self
+        }
+
+        fn prop_cells(&mut self, name: &str, cells: &[u32]) -> &mut Self {
+            let value: Vec<u8> = cells.iter().flat_map(|c| c.to_be_bytes()).collect();
+            self.prop(name, &value)
+        }
+
+        fn finish(&mut self) -> Vec<u8> {
+            self.token(FDT_END);
+
+            let off_dt_struct = HEADER_SIZE + MEM_RSVMAP_SIZE;
+            let off_dt_strings = off_dt_struct + self.structs.len();
+            let total_size = off_dt_strings + self.strings.len();
+            let header = [
+                FDT_MAGIC,
+                total_size as u32,
+                off_dt_struct as u32,
+                off_dt_strings as u32,
+                HEADER_SIZE as u32,
+                17,
+                FDT_LAST_COMP_VERSION,
+                0,
+                self.strings.len() as u32,
+                self.structs.len() as u32,
+            ];
+
+            let mut blob: Vec<u8> = header.iter().flat_map(|h| h.to_be_bytes()).collect();
+            blob.resize(off_dt_struct, 0);
+            blob.extend_from_slice(&self.structs);
+            blob.extend_from_slice(&self.strings);
+            blob
+        }
+    }
+
+    /// A tree modelled after the RPi4, with 2 address cells at the root and 1 on the `soc` bus.
+    fn blob() -> Vec<u8> {
+        Builder::default()
+            .begin_node("")
+            .prop_cells("#address-cells", &[2])
+            .prop_cells("#size-cells", &[1])
+            .begin_node("chosen")
+            .prop("bootargs", b"console=serial0\0")
+            .end_node()
+            .begin_node("memory@0")
+            .prop_cells("reg", &[0x0, 0x0, 0x3b40_0000, 0x1, 0x0, 0x8000_0000])
+            .end_node()
+            .begin_node("soc")
+            .prop("compatible", b"simple-bus\0")
+            .prop_cells("#address-cells", &[1])
+            .prop_cells("#size-cells", &[1])
+            .prop_cells("ranges", &[0x7e00_0000, 0x0, 0xfe00_0000, 0x0180_0000])
+            .begin_node("serial@7e201000")
+            .prop("compatible", b"arm,pl011\0arm,primecell\0")
+            .prop_cells("reg", &[0x7e20_1000, 0x200])
+            .end_node()
+            .begin_node("truncated@7e300000")
+            // One and a half regions, and a ranges entry lacking its size cell.
+            .prop_cells("reg", &[0x7e30_0000, 0x100, 0x7e30_1000])
+            .prop_cells("ranges", &[0x0, 0x0, 0x7e30_0000])
+            .end_node()
+            .end_node()
+            .begin_node("zero")
+            .prop_cells("#address-cells", &[0])
+            .prop_cells("#size-cells", &[0])
+            .begin_node("inner")
+            .prop_cells("#address-cells", &[0])
+            .prop_cells("#size-cells", &[0])
+            .prop_cells("reg", &[0x1])
+            .prop_cells("ranges", &[0x1])
+            .end_node()
+            .end_node()
+            .end_node()
+            .finish()
+    }
+
+    #[test]
+    fn header() {
+        let blob = blob();
+        let fdt = Fdt::new(&blob).unwrap();
+        assert_eq!(fdt.total_size(), blob.len());
+
+        // Trailing data after the total size is ignored.
+        let mut padded = blob.clone();
+        padded.extend_from_slice(&[0; 8]);
+        assert_eq!(Fdt::new(&padded).unwrap().total_size(), blob.len());
+
+        let mut bad_magic = blob.clone();
+        bad_magic[0] = 0;
+        assert_eq!(Fdt::new(&bad_magic).err(), Some(Error::BadMagic));
+
+        let mut bad_version = blob.clone();
+        bad_version[27] = FDT_LAST_COMP_VERSION as u8 + 1;
+        assert_eq!(
+            Fdt::new(&bad_version).err(),
+            Some(Error::UnsupportedVersion)
+        );
+
+        assert_eq!(
+            Fdt::new(&blob[..blob.len() - 1]).err(),
+            Some(Error::Truncated)
+        );
+        assert_eq!(
+            Fdt::new(&blob[..HEADER_SIZE - 4]).err(),
+            Some(Error::Truncated)
+        );
+    }
+
+    #[test]
+    fn find_node() {
+        let blob = blob();
+        let fdt = Fdt::new(&blob).unwrap();
+
+        assert_eq!(fdt.find_node("/").unwrap().name(), "");
+        assert_eq!(fdt.find_node("/memory").unwrap().name(), "memory@0");
+        assert_eq!(
+            fdt.find_node("/soc/serial").unwrap().name(),
+            "serial@7e201000"
+        );
+        assert_eq!(
+            fdt.find_node("/soc/serial@7e201000").unwrap().name(),
+            "serial@7e201000"
+        );
+        assert!(fdt.find_node("/soc/serial@7e201001").is_none());
+        assert!(fdt.find_node("/serial").is_none());
+
+        let names: Vec<_> = fdt.root().children().map(|c| c.name()).collect();
+        assert_eq!(names, ["chosen", "memory@0", "soc", "zero"]);
+
+        assert_eq!(
+            fdt.find_compatible("arm,primecell").unwrap().name(),
+            "serial@7e201000"
+        );
+        assert!(fdt.find_compatible("arm,gic-400").is_none());
+        assert_eq!(fdt.chosen_bootargs(), Some("console=serial0"));
+    }
+
+    #[test]
+    fn reg_with_two_address_cells() {
+        let blob = blob();
+        let fdt = Fdt::new(&blob).unwrap();
+
+        let memory: Vec<_> = fdt.memory().collect();
+        assert_eq!(
+            memory,
+            [
+                Region {
+                    address: 0x0,
+                    size: 0x3b40_0000
+                },
+                Region {
+                    address: 0x1_0000_0000,
+                    size: 0x8000_0000
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn reg_and_ranges_with_one_address_cell() {
+        let blob = blob();
+        let fdt = Fdt::new(&blob).unwrap();
+        let soc = fdt.find_node("/soc").unwrap();
+        let serial = soc.child("serial").unwrap();
+
+        assert_eq!(
+            soc.cells(),
+            Cells {
+                address: 1,
+                size: 1
+            }
+        );
+        assert_eq!(
+            serial.reg().collect::<Vec<_>>(),
+            [Region {
+                address: 0x7e20_1000,
+                size: 0x200
+            }]
+        );
+
+        // The child bus has one address cell, the root two.
+        assert_eq!(
+            soc.ranges().collect::<Vec<_>>(),
+            [Range {
+                child_address: 0x7e00_0000,
+                parent_address: 0xfe00_0000,
+                size: 0x0180_0000
+            }]
+        );
+        assert_eq!(soc.translate_address(0x7e20_1000), Some(0xfe20_1000));
+        assert_eq!(soc.translate_address(0x7f80_0000), None);
+        assert_eq!(fdt.root().translate_address(0x0), None);
+    }
+
+    #[test]
+    fn zero_cells() {
+        let blob = blob();
+        let fdt = Fdt::new(&blob).unwrap();
+        let inner = fdt.find_node("/zero/inner").unwrap();
+
+        assert_eq!(inner.reg().next(), None);
+        assert_eq!(inner.ranges().next(), None);
+    }
+
+    #[test]
+    fn truncated_properties() {
+        let blob = blob();
+        let fdt = Fdt::new(&blob).unwrap();
+        let truncated = fdt.find_node("/soc/truncated").unwrap();
+
+        let mut reg = truncated.reg();
+        assert_eq!(
+            reg.next(),
+            Some(Region {
+                address: 0x7e30_0000,
+                size: 0x100
+            })
+        );
+        assert_eq!(reg.next(), None);
+        assert_eq!(reg.next(), None);
+
+        // Default cells of the node (2 address, 1 size) and 1 parent address cell need 4 cells.
+        assert_eq!(truncated.ranges().next(), None);
+    }
+}
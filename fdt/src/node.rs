@@ -0,0 +1,369 @@
+use crate::{
+    align4, be_u32, cstr, Fdt, Property, StrList, U32Iter, FDT_BEGIN_NODE, FDT_END_NODE, FDT_NOP,
+    FDT_PROP,
+};
+
+/// Number of u32 cells used to encode addresses and sizes of child nodes, from the
+/// `#address-cells` and `#size-cells` properties.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Cells {
+    pub address: u32,
+    pub size: u32,
+}
+
+/// Default values from the devicetree specification, if the properties are absent.
+impl Default for Cells {
+    fn default() -> Self {
+        Self {
+            address: 2,
+            size: 1,
+        }
+    }
+}
+
+/// A node of the device tree.
+#[derive(Clone, Copy)]
+pub struct Node<'a> {
+    fdt: Fdt<'a>,
+
+    /// Node name including the unit address, such as `serial@7e201000`. Empty for the root node.
+    name: &'a str,
+
+    /// Offset of the first token after the node name in the structure block.
+    props_offset: usize,
+
+    /// Cells of the parent node, which apply to `reg` of this node.
+    parent_cells: Cells,
+}
+
+/// A region from a `reg` property.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Region {
+    pub address: u64,
+    pub size: u64,
+}
+
+/// An entry of a `ranges` property, which maps child bus addresses to parent bus addresses.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Range {
+    pub child_address: u64,
+    pub parent_address: u64,
+    pub size: u64,
+}
+
+/// Reads a number encoded in `cells` u32 cells at `offset`. Returns the number and the offset after
+/// it. Numbers wider than 64 bits are truncated to the low 64 bits.
+fn read_cells(data: &[u8], offset: usize, cells: u32) -> Option<(u64, usize)> {
+    let mut value = 0u64;
+    let mut offset = offset;
+
+    for _ in 0..cells {
+        value = value.checked_shl(32).unwrap_or(0) | u64::from(be_u32(data, offset)?);
+        offset += 4;
+    }
+
+    Some((value, offset))
+}
+
+impl<'a> Node<'a> {
+    /// Parses the node, which begins at `offset` of the structure block.
+    pub(crate) fn new(fdt: Fdt<'a>, offset: usize, parent_cells: Cells) -> Option<Self> {
+        if be_u32(fdt.structs, offset)? != FDT_BEGIN_NODE {
+            return None;
+        }
+
+        let name = cstr(fdt.structs.get(offset + 4..)?)?;
+
+        Some(Self {
+            fdt,
+            name,
+            props_offset: align4(offset + 4 + name.len() + 1),
+            parent_cells,
+        })
+    }
+
+    /// A node without properties and children, used if the structure block is malformed.
+    pub(crate) fn empty(fdt: Fdt<'a>) -> Self {
+        Self {
+            fdt,
+            name: "",
+            props_offset: fdt.structs.len(),
+            parent_cells: Cells::default(),
+        }
+    }
+
+    /// The node name including the unit address.
+    pub fn name(&self) -> &'a str {
+        self.name
+    }
+
+    /// Returns true if `name` equals the node name, or the node name without its unit address if
+    /// `name` has none.
+    fn matches_name(&self, name: &str) -> bool {
+        if name.contains('@') {
+            self.name == name
+        } else {
+            self.name.split('@').next() == Some(name)
+        }
+    }
+
+    /// Iterator over the properties of the node.
+    pub fn properties(&self) -> Properties<'a> {
+        Properties {
+            fdt: self.fdt,
+            offset: self.props_offset,
+        }
+    }
+
+    /// Finds a property by name.
+    pub fn property(&self, name: &str) -> Option<Property<'a>> {
+        self.properties().find(|p| p.name() == name)
+    }
+
+    /// Iterator over the direct children of the node.
+    pub fn children(&self) -> Children<'a> {
+        let mut properties = self.properties();
+        properties.by_ref().for_each(drop);
+
+        Children {
+            fdt: self.fdt,
+            offset: properties.offset,
+            cells: self.cells(),
+        }
+    }
+
+    /// Finds a direct child by name, see `Fdt::find_node()` for how unit addresses are matched.
+    pub fn child(&self, name: &str) -> Option<Node<'a>> {
+        self.children().find(|c| c.matches_name(name))
+    }
+
+    /// Cells used by the `reg` and `ranges` properties of the children of this node.
+    pub fn cells(&self) -> Cells {
+        let default = Cells::default();
+        let cells = |name, default| {
+            self.property(name)
+                .and_then(|p| p.as_u32())
+                .unwrap_or(default)
+        };
+
+        Cells {
+            address: cells("#address-cells", default.address),
+            size: cells("#size-cells", default.size),
+        }
+    }
+
+    /// The strings of the `compatible` property.
+    pub fn compatible(&self) -> StrList<'a> {
+        match self.property("compatible") {
+            Some(p) => p.as_str_list(),
+            None => StrList::empty(),
+        }
+    }
+
+    /// The regions of the `reg` property. Addresses are in the address space of the parent bus,
+    /// see `translate_address()`.
+    pub fn reg(&self) -> Regs<'a> {
+        Regs {
+            value: self.property("reg").map_or(&[], |p| p.value()),
+            offset: 0,
+            cells: self.parent_cells,
+        }
+    }
+
+    /// The raw cells of the `interrupts` property. Their meaning depends on the interrupt
+    /// controller, the RPi4 GIC-400 uses three cells per interrupt: type (0 = SPI, 1 = PPI), number
+    /// and flags.
+    pub fn interrupts(&self) -> U32Iter<'a> {
+        match self.property("interrupts") {
+            Some(p) => p.as_u32_iter(),
+            None => U32Iter::empty(),
+        }
+    }
+
+    /// The entries of the `ranges` property.
+    pub fn ranges(&self) -> Ranges<'a> {
+        Ranges {
+            value: self.property("ranges").map_or(&[], |p| p.value()),
+            offset: 0,
+            child_cells: self.cells(),
+            parent_address_cells: self.parent_cells.address,
+        }
+    }
+
+    /// Translates an address of a child of this node to the address space of this node's parent.
+    /// An empty `ranges` property is an identity mapping. Returns `None` if there is no `ranges`
+    /// property or the address is not covered by it.
+    pub fn translate_address(&self, child_address: u64) -> Option<u64> {
+        let ranges = self.property("ranges")?;
+
+        if ranges.value().is_empty() {
+            return Some(child_address);
+        }
+
+        self.ranges()
+            .find(|r| child_address >= r.child_address && child_address - r.child_address < r.size)
+            .map(|r| r.parent_address + (child_address - r.child_address))
+    }
+
+    /// Returns the offset after the END_NODE token of this node.
+    fn end_offset(&self) -> Option<usize> {
+        let structs = self.fdt.structs;
+        let mut offset = self.props_offset;
+        let mut depth = 0usize;
+
+        loop {
+            match be_u32(structs, offset)? {
+                FDT_BEGIN_NODE => {
+                    let name = cstr(structs.get(offset + 4..)?)?;
+                    offset = align4(offset + 4 + name.len() + 1);
+                    depth += 1;
+                }
+                FDT_END_NODE => {
+                    offset += 4;
+
+                    if depth == 0 {
+                        return Some(offset);
+                    }
+                    depth -= 1;
+                }
+                FDT_PROP => {
+                    let len = be_u32(structs, offset + 4)? as usize;
+                    offset = align4(offset + 12 + len);
+                }
+                FDT_NOP => offset += 4,
+                _ => return None,
+            }
+        }
+    }
+}
+
+/// Iterator over the properties of a node.
+#[derive(Clone)]
+pub struct Properties<'a> {
+    fdt: Fdt<'a>,
+    offset: usize,
+}
+
+impl<'a> Iterator for Properties<'a> {
+    type Item = Property<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let structs = self.fdt.structs;
+
+        loop {
+            match be_u32(structs, self.offset)? {
+                FDT_NOP => self.offset += 4,
+                FDT_PROP => {
+                    let len = be_u32(structs, self.offset + 4)? as usize;
+                    let name = self
+                        .fdt
+                        .string(be_u32(structs, self.offset + 8)? as usize)?;
+                    let value = structs.get(self.offset + 12..self.offset + 12 + len)?;
+
+                    self.offset = align4(self.offset + 12 + len);
+
+                    return Some(Property::new(name, value));
+                }
+                _ => return None,
+            }
+        }
+    }
+}
+
+/// Iterator over the direct children of a node.
+#[derive(Clone)]
+pub struct Children<'a> {
+    fdt: Fdt<'a>,
+    offset: usize,
+    cells: Cells,
+}
+
+impl<'a> Iterator for Children<'a> {
+    type Item = Node<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            match be_u32(self.fdt.structs, self.offset)? {
+                FDT_NOP => self.offset += 4,
+                FDT_BEGIN_NODE => {
+                    let node = Node::new(self.fdt, self.offset, self.cells)?;
+                    self.offset = node.end_offset()?;
+
+                    return Some(node);
+                }
+                _ => return None,
+            }
+        }
+    }
+}
+
+/// Iterator over the regions of a `reg` property.
+#[derive(Clone)]
+pub struct Regs<'a> {
+    value: &'a [u8],
+    offset: usize,
+    cells: Cells,
+}
+
+impl<'a> Regs<'a> {
+    pub(crate) fn empty() -> Self {
+        Self {
+            value: &[],
+            offset: 0,
+            cells: Cells::default(),
+        }
+    }
+}
+
+impl<'a> Iterator for Regs<'a> {
+    type Item = Region;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        // An entry without cells would not advance the offset.
+        if self.offset >= self.value.len() || (self.cells.address == 0 && self.cells.size == 0) {
+            return None;
+        }
+
+        let (address, offset) = read_cells(self.value, self.offset, self.cells.address)?;
+        let (size, offset) = read_cells(self.value, offset, self.cells.size)?;
+        self.offset = offset;
+
+        Some(Region { address, size })
+    }
+}
+
+/// Iterator over the entries of a `ranges` property.
+#[derive(Clone)]
+pub struct Ranges<'a> {
+    value: &'a [u8],
+    offset: usize,
+    child_cells: Cells,
+    parent_address_cells: u32,
+}
+
+impl<'a> Iterator for Ranges<'a> {
+    type Item = Range;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        // An entry without cells would not advance the offset.
+        if self.offset >= self.value.len()
+            || (self.child_cells.address == 0
+                && self.parent_address_cells == 0
+                && self.child_cells.size == 0)
+        {
+            return None;
+        }
+
+        let (child_address, offset) =
+            read_cells(self.value, self.offset, self.child_cells.address)?;
+        let (parent_address, offset) = read_cells(self.value, offset, self.parent_address_cells)?;
+        let (size, offset) = read_cells(self.value, offset, self.child_cells.size)?;
+        self.offset = offset;
+
+        Some(Range {
+            child_address,
+            parent_address,
+            size,
+        })
+    }
+}
@@ -0,0 +1,111 @@
+use crate::{be_u32, cstr};
+
+/// A property of a device tree node.
+#[derive(Clone, Copy)]
+pub struct Property<'a> {
+    name: &'a str,
+    value: &'a [u8],
+}
+
+impl<'a> Property<'a> {
+    pub(crate) fn new(name: &'a str, value: &'a [u8]) -> Self {
+        Self { name, value }
+    }
+
+    /// The property name.
+    pub fn name(&self) -> &'a str {
+        self.name
+    }
+
+    /// The raw property value.
+    pub fn value(&self) -> &'a [u8] {
+        self.value
+    }
+
+    /// The value as a single u32 cell.
+    pub fn as_u32(&self) -> Option<u32> {
+        match self.value.len() {
+            4 => be_u32(self.value, 0),
+            _ => None,
+        }
+    }
+
+    /// The value as a u64, which is encoded as one or two cells.
+    pub fn as_u64(&self) -> Option<u64> {
+        match self.value.len() {
+            4 => be_u32(self.value, 0).map(u64::from),
+            8 => {
+                Some((u64::from(be_u32(self.value, 0)?) << 32) | u64::from(be_u32(self.value, 4)?))
+            }
+            _ => None,
+        }
+    }
+
+    /// The value as a NUL terminated string.
+    pub fn as_str(&self) -> Option<&'a str> {
+        cstr(self.value)
+    }
+
+    /// The value as a list of NUL terminated strings, such as `compatible`.
+    pub fn as_str_list(&self) -> StrList<'a> {
+        StrList { data: self.value }
+    }
+
+    /// The value as a list of u32 cells.
+    pub fn as_u32_iter(&self) -> U32Iter<'a> {
+        U32Iter {
+            data: self.value,
+            offset: 0,
+        }
+    }
+}
+
+/// Iterator over a list of NUL terminated strings.
+#[derive(Clone)]
+pub struct StrList<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> StrList<'a> {
+    pub(crate) fn empty() -> Self {
+        Self { data: &[] }
+    }
+}
+
+impl<'a> Iterator for StrList<'a> {
+    type Item = &'a str;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let s = cstr(self.data)?;
+        self.data = &self.data[s.len() + 1..];
+
+        Some(s)
+    }
+}
+
+/// Iterator over a list of u32 cells.
+#[derive(Clone)]
+pub struct U32Iter<'a> {
+    data: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> U32Iter<'a> {
+    pub(crate) fn empty() -> Self {
+        Self {
+            data: &[],
+            offset: 0,
+        }
+    }
+}
+
+impl<'a> Iterator for U32Iter<'a> {
+    type Item = u32;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let value = be_u32(self.data, self.offset)?;
+        self.offset += 4;
+
+        Some(value)
+    }
+}
@@ -13,5 +13,5 @@ fn main() {
     println!("cargo:rustc-link-search={}", out.display());
 
     println!("cargo:rerun-if-changed=build.rs");
-    println!("cargo:rerun-if-changed=link.x.in");
+    println!("cargo:rerun-if-changed=link_custom.x.in");
 }
@@ -9,13 +9,23 @@ global_asm!(include_str!("boot.s"));
 #[link_section = ".text._start_arguments"]
 pub static BOOT_CORE_ID: u64 = 0;
 
+/// Device tree blob address passed by the firmware, handed on to the loaded firmware.
+static mut DTB_ADDR: usize = 0;
+
+/// Returns the device tree blob address passed by the firmware.
+pub fn dtb_addr() -> usize {
+    unsafe { DTB_ADDR }
+}
+
 #[no_mangle]
-pub unsafe extern "C" fn _start_rust() -> ! {
+pub unsafe extern "C" fn _start_rust(dtb_addr: usize) -> ! {
     extern "Rust" {
         fn main() -> !;
     }
 
     zero_bss();
+    DTB_ADDR = dtb_addr;
+
     main();
 }
 
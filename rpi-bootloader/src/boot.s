@@ -40,6 +40,9 @@
 // fn _start()
 //------------------------------------------------------------------------------
 _start:
+	// Preserve the device tree blob address passed by the firmware in x0.
+	mov	x19, x0
+
 	// Only proceed on the boot core. Park it otherwise.
 	mrs	x1, MPIDR_EL1
 	and	x1, x1, _core_id_mask
@@ -63,7 +66,8 @@ _start:
 	ADR_ABS	x0, __boot_core_stack_end_exclusive
 	mov	sp, x0
 
-	// Jump to the relocated Rust code.
+	// Jump to the relocated Rust code. x0 holds the function argument provided to _start_rust().
+	mov	x0, x19
 	ADR_ABS	x1, _start_rust
 	br	x1
 
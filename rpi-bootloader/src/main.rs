@@ -84,6 +84,7 @@ fn main() -> ! {
 
     writeln!(uart, "Jumping to firmware at {}", FW_LOAD_ADDR).ok();
 
-    let jump: fn() -> ! = unsafe { core::mem::transmute(FW_LOAD_ADDR) };
-    jump();
+    // Pass on the device tree blob address in x0, same as the firmware does.
+    let jump: extern "C" fn(usize) -> ! = unsafe { core::mem::transmute(FW_LOAD_ADDR) };
+    jump(boot::dtb_addr());
 }
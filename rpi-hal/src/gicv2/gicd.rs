@@ -3,15 +3,37 @@ use rpi_pac::gicv2::gicd::*;
 #[derive(Debug, Clone, Copy)]
 pub struct CoreMask(u8);
 
-#[derive(Debug, Clone, Copy)]
+impl CoreMask {
+    /// Creates a mask from raw bits, where bit N corresponds to core N
+    pub const fn new(mask: u8) -> Self {
+        Self(mask)
+    }
+
+    /// Creates a mask targeting a single core
+    pub const fn core(core_id: u8) -> Self {
+        Self(1 << core_id)
+    }
+
+    /// Returns raw mask bits
+    pub const fn bits(&self) -> u8 {
+        self.0
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub struct IrqNumber(u32);
 
 impl IrqNumber {
     /// TODO: add limits
-    pub fn new(num: u32) -> Self {
+    pub const fn new(num: u32) -> Self {
         Self(num)
     }
 
+    /// Returns raw IRQ number
+    pub const fn number(&self) -> u32 {
+        self.0
+    }
+
     /// Returns true if IRQ is private to the executing core
     pub fn is_private(&self) -> bool {
         // Fisrt 32 IRQs are private
@@ -0,0 +1,247 @@
+//! Inter-processor messaging over Software Generated Interrupts (SGI).
+//!
+//! Every core owns one lock-free single-producer single-consumer (SPSC) mailbox per sender core,
+//! so no locks are needed as long as each core only sends from one context at a time. The sender
+//! pushes a message into the receiver's mailbox and then rings the doorbell SGI, which makes the
+//! receiver drain its mailboxes from the IRQ handler.
+//!
+//! Mailboxes are expected to live in a `static`, which lands in normal cacheable inner-shareable
+//! memory. This requires the MMU to be enabled, otherwise atomics and cache coherency between
+//! cores are not guaranteed.
+//!
+//! ```ignore
+//! static IPI: Ipi<u32, 16> = Ipi::new(IrqNumber::new(1));
+//!
+//! // On the receiving core
+//! IPI.on_message(|sender, msg| { /* ... */ });
+//! gicd_local.enable_irq(IPI.doorbell());
+//!
+//! // In the IRQ handler of the receiving core
+//! if irq == IPI.doorbell().number() {
+//!     unsafe { IPI.dispatch() };
+//! }
+//!
+//! // On the sending core
+//! IPI.send(&mut gicd_local, 2, 42).ok();
+//! ```
+
+use core::{
+    cell::UnsafeCell,
+    mem::{self, MaybeUninit},
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use cortex_a::{barrier, regs::*};
+
+use crate::gicv2::{CoreMask, GicdLocal, IrqNumber, SgiTarget};
+
+/// Number of cores on the RPi4
+pub const NUM_CORES: usize = 4;
+
+/// Handler value, which indicates that no handler is registered
+const NO_HANDLER: usize = 0;
+
+/// Lock-free single-producer single-consumer ring buffer
+struct Mailbox<T, const CAP: usize> {
+    /// Index of the next slot to be read. Only written by the consumer.
+    head: AtomicUsize,
+    /// Index of the next slot to be written. Only written by the producer.
+    tail: AtomicUsize,
+    buf: UnsafeCell<MaybeUninit<[T; CAP]>>,
+}
+
+impl<T, const CAP: usize> Mailbox<T, CAP> {
+    const fn new() -> Self {
+        Self {
+            head: AtomicUsize::new(0),
+            tail: AtomicUsize::new(0),
+            buf: UnsafeCell::new(MaybeUninit::uninit()),
+        }
+    }
+
+    /// Pointer to the slot at the given (unwrapped) index
+    fn slot(&self, index: usize) -> *mut T {
+        unsafe { ((*self.buf.get()).as_mut_ptr() as *mut T).add(index % CAP) }
+    }
+
+    /// Pushes a message into the queue, returning it back if the queue is full.
+    ///
+    /// # Safety
+    ///
+    /// - Must only be called by a single producer at a time.
+    unsafe fn push(&self, msg: T) -> Result<(), T> {
+        let tail = self.tail.load(Ordering::Relaxed);
+        let head = self.head.load(Ordering::Acquire);
+
+        if tail.wrapping_sub(head) >= CAP {
+            return Err(msg);
+        }
+
+        self.slot(tail).write(msg);
+        self.tail.store(tail.wrapping_add(1), Ordering::Release);
+
+        Ok(())
+    }
+
+    /// Pops a message from the queue.
+    ///
+    /// # Safety
+    ///
+    /// - Must only be called by a single consumer at a time.
+    unsafe fn pop(&self) -> Option<T> {
+        let head = self.head.load(Ordering::Relaxed);
+        let tail = self.tail.load(Ordering::Acquire);
+
+        if head == tail {
+            return None;
+        }
+
+        let msg = self.slot(head).read();
+        self.head.store(head.wrapping_add(1), Ordering::Release);
+
+        Some(msg)
+    }
+}
+
+/// Mailboxes of a single receiver core, indexed by the sender core
+type MailboxRow<T, const CAP: usize> = [Mailbox<T, CAP>; NUM_CORES];
+
+/// Inter-processor messaging channel with `CAP` messages of buffering per sender-receiver pair
+pub struct Ipi<T, const CAP: usize> {
+    /// SGI used to notify the receiving core
+    doorbell: IrqNumber,
+    /// Mailboxes indexed by `[receiver][sender]`
+    mailboxes: [MailboxRow<T, CAP>; NUM_CORES],
+    /// Message handler function pointers, indexed by the receiving core
+    handlers: [AtomicUsize; NUM_CORES],
+}
+
+// Every mailbox has exactly one producer and one consumer core, see `send()` and `dispatch()`.
+unsafe impl<T: Send, const CAP: usize> Sync for Ipi<T, CAP> {}
+
+/// Returns number of the core which is currently executing this function
+fn current_core() -> usize {
+    (MPIDR_EL1.get() & 0b11) as usize
+}
+
+impl<T, const CAP: usize> Ipi<T, CAP> {
+    /// Creates a new messaging channel, which uses the given SGI as a doorbell.
+    pub const fn new(doorbell: IrqNumber) -> Self {
+        Self {
+            doorbell,
+            mailboxes: [
+                Self::new_row(),
+                Self::new_row(),
+                Self::new_row(),
+                Self::new_row(),
+            ],
+            handlers: [
+                AtomicUsize::new(NO_HANDLER),
+                AtomicUsize::new(NO_HANDLER),
+                AtomicUsize::new(NO_HANDLER),
+                AtomicUsize::new(NO_HANDLER),
+            ],
+        }
+    }
+
+    const fn new_row() -> MailboxRow<T, CAP> {
+        [
+            Mailbox::new(),
+            Mailbox::new(),
+            Mailbox::new(),
+            Mailbox::new(),
+        ]
+    }
+
+    /// Returns the SGI which must be enabled on every receiving core
+    pub fn doorbell(&self) -> IrqNumber {
+        self.doorbell
+    }
+
+    /// Registers a message handler for the executing core.
+    ///
+    /// The handler is called from `dispatch()` with the number of the sender core and the message.
+    pub fn on_message(&self, handler: fn(u8, T)) {
+        self.handlers[current_core()].store(handler as usize, Ordering::Release);
+    }
+
+    /// Pushes a message into the mailbox of `core` without ringing the doorbell.
+    fn push(&self, core: usize, msg: T) -> Result<(), T> {
+        // Mask IRQs, so that the mailbox is not pushed to from the interrupt context at the same
+        // time, which would break the single producer guarantee.
+        let daif = DAIF.get();
+        DAIF.modify(DAIF::I::Masked);
+
+        let res = unsafe { self.mailboxes[core][current_core()].push(msg) };
+
+        DAIF.set(daif);
+
+        res
+    }
+
+    /// Rings the doorbell on the target cores
+    fn ring(&self, gicd: &mut GicdLocal, target: SgiTarget) {
+        // Ensure that mailbox writes are observable by other cores before the SGI arrives.
+        barrier::dsb(barrier::SY);
+
+        gicd.pend_sgi(self.doorbell, target);
+    }
+
+    /// Sends a message to the given core. Returns the message back if the mailbox is full.
+    pub fn send(&self, gicd: &mut GicdLocal, core: u8, msg: T) -> Result<(), T> {
+        if core as usize >= NUM_CORES {
+            panic!("Attempted to send IPI message to non-existent core {}", core);
+        }
+
+        self.push(core as usize, msg)?;
+        self.ring(gicd, SgiTarget::Mask(CoreMask::core(core)));
+
+        Ok(())
+    }
+
+    /// Sends a copy of the message to all cores except the executing one.
+    ///
+    /// Returns a mask of cores, which did not receive the message because their mailbox was full.
+    pub fn broadcast(&self, gicd: &mut GicdLocal, msg: T) -> Result<(), CoreMask>
+    where
+        T: Copy,
+    {
+        let mut missed = 0u8;
+
+        for core in (0..NUM_CORES).filter(|&core| core != current_core()) {
+            if self.push(core, msg).is_err() {
+                missed |= 1 << core;
+            }
+        }
+
+        self.ring(gicd, SgiTarget::AllExceptCurrent);
+
+        if missed == 0 {
+            Ok(())
+        } else {
+            Err(CoreMask::new(missed))
+        }
+    }
+
+    /// Drains all mailboxes of the executing core and calls the registered message handler.
+    ///
+    /// Messages are left in the mailboxes if no handler is registered.
+    ///
+    /// # Safety
+    ///
+    /// - Must only be called from the doorbell IRQ context of the executing core.
+    pub unsafe fn dispatch(&self) {
+        let core = current_core();
+
+        let handler = match self.handlers[core].load(Ordering::Acquire) {
+            NO_HANDLER => return,
+            handler => mem::transmute::<usize, fn(u8, T)>(handler),
+        };
+
+        for (sender, mailbox) in self.mailboxes[core].iter().enumerate() {
+            while let Some(msg) = mailbox.pop() {
+                handler(sender as u8, msg);
+            }
+        }
+    }
+}
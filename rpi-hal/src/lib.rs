@@ -2,6 +2,7 @@
 
 pub mod gicv2;
 pub mod gpio;
+pub mod ipi;
 pub mod serial;
 pub mod time;
 
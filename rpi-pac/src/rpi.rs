@@ -5,7 +5,11 @@ mod rpi4 {
     use crate::{gicv2, gpio, uart};
     use core::{marker::PhantomData, ops::Deref};
 
+    /// Addresses, at which the runtime maps the peripherals. With the `higher-half` feature of
+    /// `cortex-a-rt`, these are the high aliases of the physical addresses.
     pub mod mmio {
+        use cortex_a_rt::memory::phys_to_virt;
+
         pub const GPIO_OFFSET: usize = 0x0020_0000;
         pub const UART0_OFFSET: usize = 0x0020_1000;
         pub const UART2_OFFSET: usize = 0x0020_1400;
@@ -13,15 +17,15 @@ mod rpi4 {
         pub const UART4_OFFSET: usize = 0x0020_1800;
         pub const UART5_OFFSET: usize = 0x0020_1A00;
 
-        pub const START: usize = 0xFE00_0000;
+        pub const START: usize = phys_to_virt(0xFE00_0000);
         pub const GPIO_START: usize = START + GPIO_OFFSET;
         pub const UART0_START: usize = START + UART0_OFFSET;
         pub const UART2_START: usize = START + UART2_OFFSET;
         pub const UART3_START: usize = START + UART3_OFFSET;
         pub const UART4_START: usize = START + UART4_OFFSET;
         pub const UART5_START: usize = START + UART5_OFFSET;
-        pub const GICD_START: usize = 0xFF84_1000;
-        pub const GICC_START: usize = 0xFF84_2000;
+        pub const GICD_START: usize = phys_to_virt(0xFF84_1000);
+        pub const GICC_START: usize = phys_to_virt(0xFF84_2000);
     }
 
     pub struct Gpio {